@@ -1,11 +1,48 @@
+use super::cartridge::Cartridge;
 use super::consts;
+use super::device::{BusDevice, Devices};
+use super::dma::Dma;
+use super::model::Model;
+use super::save_state::{self, SaveStateError, StateReader, StateWriter};
+use super::scheduler::{Peripheral, Scheduler};
+use super::serial::Serial;
+use super::timer::Timer;
+use std::fmt;
+use std::ops::RangeInclusive;
+mod io;
+mod oam_bug;
+pub use oam_bug::OamCorruption;
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, PartialEq)]
+pub enum BootRomError {
+    WrongSize {
+        model: Model,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for BootRomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BootRomError::WrongSize {
+                model,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{:?} boot ROM should be {} bytes, got {}",
+                model, expected, found
+            ),
+        }
+    }
+}
 
 pub struct AddrSpace {
-    bios: [u8; 0x0100],
-    bank0: [u8; 0x4000],
-    bank1: [u8; 0x4000],
+    bios: Vec<u8>,
     video_ram: [u8; 0x2000],
-    external_ram: [u8; 0x2000],
     work_ram1: [u8; 0x1000],
     work_ram2: [u8; 0x1000],
     sprite_table: [u8; 0xA0],
@@ -13,173 +50,602 @@ pub struct AddrSpace {
     hram: [u8; 0x7F],
     interrupt_enable_register: u8,
 
-    memory_model: u8,
-    rom_bank: u8,
-    cartridge: Option<Vec<u8>>,
+    // The cartridge and any other hardware plugged into the bus
+    devices: Devices,
+    // Identifies the cartridge in save states
+    rom_hash: u64,
+    model: Model,
     running_bios: bool,
+    // Set when the boot ROM isn't run and the post-boot state is set up directly
+    skip_boot: bool,
+    stat_written: bool,
+    // Set by writes to the LCD registers, the PPU has to look at them on its next dot
+    lcd_written: bool,
+    // Pressed inputs, directions in the low nibble and buttons in the high one
+    joypad_buttons: u8,
+    dma: Dma,
+    timer: Timer,
+    serial: Serial,
+    // What the serial port sent, when a frontend asked for it with log_serial
+    serial_log: Option<Vec<u8>>,
+    scheduler: Scheduler,
+    // Blocks CPU access to VRAM during mode 3 and to OAM during modes 2 and 3
+    restrict_access: bool,
 }
 
 impl AddrSpace {
     pub fn read(&self, addr: u16) -> u8 {
+        if self.boot_rom_mapped(addr) {
+            return self.bios[addr as usize];
+        }
+        if let Some(data) = self.devices.read(addr) {
+            return data;
+        }
         match addr {
-            0x0..=0xFF if self.running_bios => self.bios[addr as usize],
-            0x0..=0x3FFF => self.bank0[addr as usize],
-            0x4000..=0x7FFF => self.bank1[(addr - 0x4000) as usize],
+            // Nothing drives the bus without a cartridge
+            0x0..=0x7FFF | 0xA000..=0xBFFF => 0xFF,
             0x8000..=0x9FFF => self.video_ram[(addr - 0x8000) as usize],
-            0xA000..=0xBFFF => self.external_ram[(addr - 0xA000) as usize],
             0xC000..=0xCFFF => self.work_ram1[(addr - 0xC000) as usize],
             0xD000..=0xDFFF => self.work_ram2[(addr - 0xD000) as usize],
             0xE000..=0xFDFF => self.read(addr - 0x2000),
             0xFE00..=0xFE9F => self.sprite_table[(addr - 0xFE00) as usize],
-            0xFEA0..=0xFEFF => {
-                println!("Prohibited memory address (read)");
-                0x00
-            }
-            0xFF00..=0xFF7F => self.io_registers[(addr - 0xFF00) as usize],
+            0xFEA0..=0xFEFF => self.unusable_read(addr),
+            0xFF00..=0xFF7F => self.read_io(addr),
             0xFF80..=0xFFFE => self.hram[(addr - 0xFF80) as usize],
             0xFFFF => self.interrupt_enable_register,
         }
     }
 
     pub fn write(&mut self, addr: u16, data: u8) {
+        if self.devices.write(addr, data) {
+            return;
+        }
         match addr {
-            0xff46 => {
-                for i in 0..0x9F {
-                    self.write(0xFE00 | i, self.read(((data as u16) << 8) | i));
-                }
-            }
-            //TODO: cover all the cartridge types and memory models :(
-            0x6000..=0x7FFF if *self.cartridge_type() == 1 => {
-                self.memory_model = 0x1 & data;
-            }
-            0x2000..=0x3FFF if *self.cartridge_type() == 1 => {
-                self.rom_bank = 0x1.max(0x1F & data);
-                self.load_bank(self.rom_bank);
-            }
-            0x0..=0x3FFF => self.bank0[addr as usize] = data,
-            0x4000..=0x7FFF => self.bank1[(addr - 0x4000) as usize] = data,
+            0x0..=0x7FFF | 0xA000..=0xBFFF => {}
             0x8000..=0x9FFF => self.video_ram[(addr - 0x8000) as usize] = data,
-            0xA000..=0xBFFF => self.external_ram[(addr - 0xA000) as usize] = data,
             0xC000..=0xCFFF => self.work_ram1[(addr - 0xC000) as usize] = data,
             0xD000..=0xDFFF => self.work_ram2[(addr - 0xD000) as usize] = data,
             0xE000..=0xFDFF => self.write(addr - 0x2000, data),
             0xFE00..=0xFE9F => self.sprite_table[(addr - 0xFE00) as usize] = data,
-            0xFEA0..=0xFEFF => println!("Prohibited memory address {:04x} (write)", addr),
-            0xFF00..=0xFF7F => self.io_registers[(addr - 0xFF00) as usize] = data,
+            // Writes to the unusable area are ignored
+            0xFEA0..=0xFEFF => {}
+            0xFF00..=0xFF7F => self.write_io(addr, data),
             0xFF80..=0xFFFE => self.hram[(addr - 0xFF80) as usize] = data,
             0xFFFF => self.interrupt_enable_register = data,
         };
     }
 
-    pub fn deactivate_bios(&mut self) {
-        self.running_bios = false;
+    fn read_io(&self, addr: u16) -> u8 {
+        let offset = (addr - 0xFF00) as usize;
+        match addr {
+            consts::JOYPAD_ADDR => self.joypad_register(),
+            // The timer is run on a copy, only what it would read now matters
+            consts::DIV_ADDR..=consts::TAC_ADDR => {
+                let mut timer = self.timer;
+                timer.advance(self.scheduler.elapsed(Peripheral::Timer) / 4);
+                timer.read(addr) | io::read_mask(self.model, offset)
+            }
+            consts::SB_ADDR | consts::SC_ADDR => {
+                let mut serial = self.serial;
+                serial.advance(self.scheduler.elapsed(Peripheral::Serial) / 4);
+                serial.read(addr) | io::read_mask(self.model, offset)
+            }
+            _ => self.io_registers[offset] | io::read_mask(self.model, offset),
+        }
+    }
+
+    fn write_io(&mut self, addr: u16, data: u8) {
+        let offset = (addr - 0xFF00) as usize;
+        if (consts::LCDC_ADDR..=consts::WX_ADDR).contains(&addr) {
+            self.lcd_written = true;
+        }
+        match addr {
+            consts::LCD_STAT_ADDR => {
+                let mask = io::write_mask(offset);
+                self.io_registers[offset] = (self.io_registers[offset] & !mask) | (data & mask);
+                self.stat_written = true;
+            }
+            // Only the select bits of the joypad register are writable
+            consts::JOYPAD_ADDR => {
+                let lines = self.joypad_register();
+                self.io_registers[0] = data & 0x30;
+                self.joypad_interrupt(lines);
+            }
+            consts::DIV_ADDR..=consts::TAC_ADDR => {
+                self.sync_timer();
+                self.timer.write(addr, data);
+                self.schedule_timer();
+            }
+            consts::SB_ADDR | consts::SC_ADDR => {
+                self.sync_serial();
+                let sent = self.serial.write(addr, data, self.model);
+                if let (Some(byte), Some(log)) = (sent, &mut self.serial_log) {
+                    log.push(byte);
+                }
+                self.schedule_serial();
+            }
+            0xFF46 => {
+                self.sync_dma();
+                self.io_registers[offset] = data;
+                self.dma.start(data);
+                self.schedule_dma();
+            }
+            // The boot ROM unmaps itself by writing 1 here, it can't be mapped back until reset
+            consts::BOOT_ROM_ADDR => {
+                if data & 0x1 != 0 {
+                    self.running_bios = false;
+                }
+            }
+            _ => {
+                let mask = io::write_mask(offset);
+                self.io_registers[offset] = (self.io_registers[offset] & !mask) | (data & mask);
+            }
+        }
+    }
+
+    // Nothing is connected to FEA0-FEFF. DMG-family models read 0, the CGB and AGB return the
+    // high nibble of the address twice
+    fn unusable_read(&self, addr: u16) -> u8 {
+        if self.model.is_cgb() {
+            let nibble = addr as u8 & 0xF0;
+            nibble | nibble >> 4
+        } else {
+            0x00
+        }
+    }
+
+    // The boot ROM covers the start of the cartridge, except for its header at 0x100-0x1FF
+    fn boot_rom_mapped(&self, addr: u16) -> bool {
+        self.running_bios && !(0x100..=0x1FF).contains(&addr) && (addr as usize) < self.bios.len()
+    }
+
+    // Used by the PPU to update the read-only bits of STAT
+    pub fn set_stat(&mut self, value: u8) {
+        self.io_registers[(consts::LCD_STAT_ADDR - 0xFF00) as usize] = value;
+    }
+
+    // Used by the PPU to move to the next line, LY can't be written by the CPU
+    pub fn set_ly(&mut self, value: u8) {
+        self.io_registers[(consts::LY_ADDR - 0xFF00) as usize] = value;
+    }
+
+    // Updates the pressed inputs, see JoypadState
+    pub fn set_joypad_buttons(&mut self, pressed: u8) {
+        let lines = self.joypad_register();
+        self.joypad_buttons = pressed;
+        self.joypad_interrupt(lines);
+    }
+
+    // P10-P13 are pulled low by the pressed inputs of every selected group
+    fn joypad_register(&self) -> u8 {
+        let select = self.io_registers[0] & 0x30;
+        let mut lines = 0x0F;
+        if select & 0x10 == 0 {
+            lines &= !(self.joypad_buttons & 0x0F);
+        }
+        if select & 0x20 == 0 {
+            lines &= !(self.joypad_buttons >> 4);
+        }
+        0xC0 | select | lines
+    }
+
+    // Any input line going from high to low requests the joypad interrupt
+    fn joypad_interrupt(&mut self, old_lines: u8) {
+        if old_lines & !self.joypad_register() & 0x0F != 0 {
+            self.set_if_joypad(true);
+        }
+    }
+
+    pub fn lcd_written(&self) -> bool {
+        self.lcd_written
+    }
+
+    pub fn take_lcd_write(&mut self) -> bool {
+        std::mem::replace(&mut self.lcd_written, false)
+    }
+
+    // Returns true once after each CPU write to STAT
+    pub fn take_stat_write(&mut self) -> bool {
+        std::mem::replace(&mut self.stat_written, false)
+    }
+
+    // Memory accesses made by the CPU. While an OAM DMA is running the CPU can only use the
+    // FF00-FFFF area: OAM reads 0xFF and reads from the bus the DMA is using return the byte
+    // being transferred. The DMA and the device at the address are brought up to date first
+    pub fn cpu_read(&mut self, addr: u16) -> u8 {
+        self.sync_for_cpu(addr);
+        if self.ppu_blocks(addr) {
+            return 0xFF;
+        }
+        if self.dma.active() {
+            match addr {
+                0xFE00..=0xFEFF => return 0xFF,
+                0xFF00..=0xFFFF => {}
+                _ if same_bus(addr, self.dma.source()) => return self.dma.last_byte(),
+                _ => {}
+            }
+        }
+        self.read(addr)
+    }
+
+    pub fn cpu_write(&mut self, addr: u16, data: u8) {
+        self.sync_for_cpu(addr);
+        if self.ppu_blocks(addr) {
+            return;
+        }
+        if self.dma.active() {
+            match addr {
+                0xFE00..=0xFEFF => return,
+                0xFF00..=0xFFFF => {}
+                _ if same_bus(addr, self.dma.source()) => return,
+                _ => {}
+            }
+        }
+        self.write(addr, data);
+        // The write can move the device's next event
+        if self.devices.maps(addr) {
+            self.schedule_devices();
+        }
+    }
+
+    fn sync_for_cpu(&mut self, addr: u16) {
+        if self.dma.running() {
+            self.sync_dma();
+        }
+        if self.devices.maps(addr) {
+            self.sync_devices();
+        }
+    }
+
+    // The PPU owns VRAM while it draws (mode 3) and OAM while it scans or draws (modes 2 and 3).
+    // The unusable area after OAM reads 0xFF too while OAM is blocked
+    fn ppu_blocks(&self, addr: u16) -> bool {
+        if !self.restrict_access {
+            return false;
+        }
+        let mode = self.read(consts::LCD_STAT_ADDR) & 0x3;
+        match addr {
+            0x8000..=0x9FFF => mode == 3,
+            0xFE00..=0xFEFF => mode >= 2,
+            _ => false,
+        }
+    }
+
+    // Only DMG-family models have the OAM corruption bug, see PPU::oam_row. It happens
+    // whether or not the access restrictions are on
+    pub fn corrupt_oam(&mut self, row: usize, corruption: OamCorruption) {
+        if !self.model.is_cgb() {
+            oam_bug::corrupt(&mut self.sprite_table, row, corruption);
+        }
+    }
+
+    pub fn scheduler(&mut self) -> &mut Scheduler {
+        &mut self.scheduler
+    }
+
+    // Runs the timer up to now and raises its interrupt if it overflowed on the way
+    pub fn sync_timer(&mut self) {
+        let dots = self.scheduler.catch_up(Peripheral::Timer);
+        if self.timer.advance(dots / 4) {
+            self.set_if_timer(true);
+        }
+        self.schedule_timer();
+    }
+
+    // STOP resets DIV and cancels the timer's event. The dots until the CPU wakes up are then
+    // dropped instead of run
+    pub fn stop_timer(&mut self, stopped: bool) {
+        if stopped {
+            self.sync_timer();
+            self.timer.write(consts::DIV_ADDR, 0);
+            self.scheduler.cancel(Peripheral::Timer);
+        } else {
+            self.scheduler.catch_up(Peripheral::Timer);
+            self.schedule_timer();
+        }
+    }
+
+    fn schedule_timer(&mut self) {
+        match self.timer.m_cycles_to_interrupt() {
+            Some(m_cycles) => self.scheduler.schedule(Peripheral::Timer, m_cycles * 4),
+            None => self.scheduler.cancel(Peripheral::Timer),
+        }
+    }
+
+    // Runs the serial port up to now and raises its interrupt if a transfer ended on the way
+    pub fn sync_serial(&mut self) {
+        let dots = self.scheduler.catch_up(Peripheral::Serial);
+        if self.serial.advance(dots / 4) {
+            self.set_if_serial(true);
+        }
+        self.schedule_serial();
+    }
+
+    fn schedule_serial(&mut self) {
+        match self.serial.m_cycles_to_interrupt() {
+            Some(m_cycles) => self.scheduler.schedule(Peripheral::Serial, m_cycles * 4),
+            None => self.scheduler.cancel(Peripheral::Serial),
+        }
+    }
+
+    // Starts or stops keeping the bytes sent through the serial port, test ROMs print their
+    // results this way. The log isn't part of save states and survives resets
+    pub fn log_serial(&mut self, enabled: bool) {
+        if enabled != self.serial_log.is_some() {
+            self.serial_log = enabled.then(Vec::new);
+        }
+    }
+
+    // Everything sent since logging started
+    pub fn serial_log(&self) -> &[u8] {
+        self.serial_log.as_deref().unwrap_or_default()
+    }
+
+    // Homebrew can be debugged with the restrictions turned off
+    pub fn set_access_restrictions(&mut self, enabled: bool) {
+        self.restrict_access = enabled;
+    }
+
+    // Runs the OAM DMA up to now, the copy only has to be up to date when the CPU or the PPU
+    // look at memory
+    pub fn sync_dma(&mut self) {
+        let dots = self.scheduler.catch_up(Peripheral::Dma);
+        if self.dma.running() {
+            self.tick_dma(dots / 4);
+            self.schedule_dma();
+        }
+    }
+
+    fn schedule_dma(&mut self) {
+        match self.dma.m_cycles_left() {
+            0 => self.scheduler.cancel(Peripheral::Dma),
+            m_cycles => self.scheduler.schedule(Peripheral::Dma, m_cycles * 4),
+        }
+    }
+
+    // Advances the OAM DMA by the given number of M-cycles
+    pub fn tick_dma(&mut self, m_cycles: u64) {
+        for _ in 0..m_cycles.min(self.dma.m_cycles_left()) {
+            if let Some(source) = self.dma.next_source() {
+                // Pages above DF read from the echo of work RAM
+                let source = if source >= 0xE000 {
+                    source - 0x2000
+                } else {
+                    source
+                };
+                let byte = self.read(source);
+                let offset = self.dma.copied(byte);
+                self.sprite_table[offset] = byte;
+            }
+        }
     }
 
     pub fn empty() -> AddrSpace {
         AddrSpace {
-            bios: [0; 0x0100],
-            bank0: [0; 0x4000],
-            bank1: [0; 0x4000],
+            bios: Vec::new(),
             video_ram: [0x00; 0x2000],
-            external_ram: [0; 0x2000],
             work_ram1: [0; 0x1000],
             work_ram2: [0; 0x1000],
             sprite_table: [0; 0xA0],
             io_registers: [0xff; 0x80],
             hram: [0; 0x7F],
             interrupt_enable_register: 0,
-            memory_model: 0,
-            rom_bank: 0,
-            cartridge: None,
+            devices: Devices::new(),
+            rom_hash: 0,
+            model: Model::DMG,
             running_bios: false,
+            skip_boot: false,
+            stat_written: false,
+            lcd_written: false,
+            joypad_buttons: 0,
+            dma: Dma::new(),
+            timer: Timer::new(),
+            serial: Serial::new(),
+            serial_log: None,
+            scheduler: Scheduler::new(),
+            restrict_access: true,
         }
     }
 
     pub fn new(bios: [u8; 0x100], cartridge: Option<Vec<u8>>) -> AddrSpace {
+        AddrSpace::with_bios(Model::DMG, bios.to_vec(), cartridge)
+    }
+
+    // Runs a boot ROM dump of the given model, e.g. one loaded from a file
+    pub fn with_boot_rom(
+        model: Model,
+        boot_rom: Vec<u8>,
+        cartridge: Option<Vec<u8>>,
+    ) -> Result<AddrSpace, BootRomError> {
+        if boot_rom.len() != model.boot_rom_size() {
+            return Err(BootRomError::WrongSize {
+                model,
+                expected: model.boot_rom_size(),
+                found: boot_rom.len(),
+            });
+        }
+        Ok(AddrSpace::with_bios(model, boot_rom, cartridge))
+    }
+
+    fn with_bios(model: Model, bios: Vec<u8>, cartridge: Option<Vec<u8>>) -> AddrSpace {
         let mut addr_space = AddrSpace {
             bios,
-            bank0: [0; 0x4000],
-            bank1: [0; 0x4000],
             video_ram: [0x00; 0x2000],
-            external_ram: [0; 0x2000],
             work_ram1: [0; 0x1000],
             work_ram2: [0; 0x1000],
             sprite_table: [0; 0xA0],
             io_registers: [0; 0x80],
             hram: [0; 0x7F],
             interrupt_enable_register: 0,
-            memory_model: 0,
-            rom_bank: 0,
-            cartridge,
+            devices: Devices::new(),
+            rom_hash: 0,
+            model,
             running_bios: true,
+            skip_boot: false,
+            stat_written: false,
+            lcd_written: false,
+            joypad_buttons: 0,
+            dma: Dma::new(),
+            timer: Timer::new(),
+            serial: Serial::new(),
+            serial_log: None,
+            scheduler: Scheduler::new(),
+            restrict_access: true,
         };
 
-        addr_space.load_cartridge_head();
+        // Without a cartridge the CPU runs into an empty ROM
+        let rom = cartridge.unwrap_or_else(|| vec![0; 0x8000]);
+        addr_space.rom_hash = save_state::rom_hash(&rom);
+        addr_space.map_device(
+            &[0x0000..=0x7FFF, 0xA000..=0xBFFF],
+            Box::new(Cartridge::new(rom)),
+        );
+        addr_space
+    }
 
-        println!("Game title: {}", addr_space.game_title());
-        println!("Cartridge type: {}", *addr_space.cartridge_type());
-        println!("Rom size: {}", *addr_space.rom_size());
+    // Starts with the state the model's boot ROM leaves behind, without running it
+    pub fn post_boot(model: Model, cartridge: Option<Vec<u8>>) -> AddrSpace {
+        let mut addr_space = AddrSpace::with_bios(model, Vec::new(), cartridge);
+        addr_space.running_bios = false;
+        addr_space.skip_boot = true;
+        addr_space.io_registers = model.post_boot_io();
+        addr_space.timer = Timer::from_registers(&addr_space.io_registers);
         addr_space
     }
 
-    fn load_bank(&mut self, bank: u8) {
-        if let Some(cart) = &self.cartridge {
-            let bank_start = bank as usize * 0x4000;
-            let bank_end = bank_start + 0x4000;
-            self.bank1.clone_from_slice(&cart[bank_start..bank_end]);
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    // True when the boot ROM is skipped, the CPU and PPU then start from the model's post-boot
+    // state, see Model::post_boot_cpu and PPU::post_boot
+    pub fn skips_boot(&self) -> bool {
+        self.skip_boot
+    }
+
+    // Plugs a device into the given address ranges, over whatever was mapped there before
+    pub fn map_device(&mut self, ranges: &[RangeInclusive<u16>], device: Box<dyn BusDevice>) {
+        self.devices.map(ranges, device);
+    }
+
+    // Runs the plugged devices up to now and raises the interrupts they request
+    pub fn sync_devices(&mut self) {
+        let dots = self.scheduler.catch_up(Peripheral::Devices);
+        let interrupts = self.devices.tick(dots / 4);
+        if interrupts != 0 {
+            let if_value = self.read(consts::IF_ADDR) | interrupts;
+            self.write(consts::IF_ADDR, if_value);
         }
+        self.schedule_devices();
     }
 
-    fn load_cartridge_head(&mut self) {
-        if let Some(cart) = &self.cartridge {
-            self.bank0[0x000..=0x3FFF].clone_from_slice(&cart[0x000..=0x3FFF]);
-            self.bank1[0..=0x3FFF].clone_from_slice(&cart[0x4000..=0x7FFF]);
+    fn schedule_devices(&mut self) {
+        match self.devices.next_event() {
+            Some(m_cycles) => self.scheduler.schedule(Peripheral::Devices, m_cycles * 4),
+            None => self.scheduler.cancel(Peripheral::Devices),
         }
     }
 
     pub fn reset(&mut self) {
-        self.bank0 = [0; 0x4000];
-        self.bank1 = [0; 0x4000];
         self.video_ram = [0x00; 0x2000];
-        self.external_ram = [0; 0x2000];
         self.work_ram1 = [0; 0x1000];
         self.work_ram2 = [0; 0x1000];
         self.sprite_table = [0; 0xA0];
-        self.io_registers = [0; 0x80];
+        self.io_registers = if self.skip_boot {
+            self.model.post_boot_io()
+        } else {
+            [0; 0x80]
+        };
         self.hram = [0; 0x7F];
         self.interrupt_enable_register = 0;
-        self.memory_model = 0;
-        self.rom_bank = 0;
-        self.running_bios = true;
-        self.load_cartridge_head();
+        self.devices.reset();
+        self.running_bios = !self.skip_boot;
+        self.stat_written = false;
+        self.lcd_written = false;
+        self.joypad_buttons = 0;
+        self.dma = Dma::new();
+        self.timer = if self.skip_boot {
+            Timer::from_registers(&self.io_registers)
+        } else {
+            Timer::new()
+        };
+        self.serial = Serial::new();
+        self.scheduler = Scheduler::new();
+    }
+
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    // The boot ROM, the model and the access restrictions are part of the setup rather than
+    // the state
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.video_ram);
+        state.write_bytes(&self.work_ram1);
+        state.write_bytes(&self.work_ram2);
+        state.write_bytes(&self.sprite_table);
+        state.write_bytes(&self.io_registers);
+        state.write_bytes(&self.hram);
+        state.write_u8(self.interrupt_enable_register);
+        self.devices.save_state(state);
+        state.write_bool(self.running_bios);
+        state.write_bool(self.stat_written);
+        state.write_bool(self.lcd_written);
+        state.write_u8(self.joypad_buttons);
+        self.dma.save_state(state);
+        self.scheduler.save_state(state);
+        self.timer.save_state(state);
+        self.serial.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        state.read_bytes(&mut self.video_ram)?;
+        state.read_bytes(&mut self.work_ram1)?;
+        state.read_bytes(&mut self.work_ram2)?;
+        state.read_bytes(&mut self.sprite_table)?;
+        state.read_bytes(&mut self.io_registers)?;
+        state.read_bytes(&mut self.hram)?;
+        self.interrupt_enable_register = state.read_u8()?;
+        self.devices.load_state(state)?;
+        self.running_bios = state.read_bool()?;
+        self.stat_written = state.read_bool()?;
+        self.lcd_written = state.read_bool()?;
+        self.joypad_buttons = state.read_u8()?;
+        self.dma.load_state(state)?;
+        self.scheduler.load_state(state)?;
+        self.timer.load_state(state)?;
+        self.serial.load_state(state)
     }
 
     // Cartridge info
 
     pub fn game_title(&self) -> String {
-        std::str::from_utf8(&self.bank0[0x134..=0x142])
-            .unwrap()
-            .replace(0 as char, "")
+        let title: Vec<u8> = (0x134..=0x142).map(|addr| self.read(addr)).collect();
+        String::from_utf8_lossy(&title).replace(0 as char, "")
     }
 
     pub fn is_color_gb(&self) -> bool {
-        self.bank0[0x143] == 0x80
+        self.read(0x143) == 0x80
     }
 
-    pub fn gb_indicator(&self) -> &u8 {
-        &self.bank0[0x146]
+    pub fn gb_indicator(&self) -> u8 {
+        self.read(0x146)
     }
 
-    pub fn cartridge_type(&self) -> &u8 {
-        &self.bank0[0x147]
+    pub fn cartridge_type(&self) -> u8 {
+        self.read(0x147)
     }
 
-    pub fn rom_size(&self) -> &u8 {
-        &self.bank0[0x148]
+    pub fn header_checksum(&self) -> u8 {
+        self.read(0x14D)
     }
 
-    pub fn ram_size(&self) -> &u8 {
-        &self.bank0[0x149]
+    pub fn rom_size(&self) -> u8 {
+        self.read(0x148)
+    }
+
+    pub fn ram_size(&self) -> u8 {
+        self.read(0x149)
     }
 
     pub fn bg_tile_map_area(&self) -> bool {
@@ -267,3 +733,9 @@ impl AddrSpace {
         self.set_if_flag(value, 4);
     }
 }
+
+// VRAM sits on its own bus, everything else below FE00 shares the external bus
+fn same_bus(addr1: u16, addr2: u16) -> bool {
+    let vram = |addr| (0x8000..=0x9FFF).contains(&addr);
+    vram(addr1) == vram(addr2)
+}
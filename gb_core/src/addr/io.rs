@@ -0,0 +1,51 @@
+use super::Model;
+
+// Bits of the IO registers that always read as 1, indexed by the offset from FF00. Unused
+// bits, write-only registers and unmapped addresses read 0xFF whatever was written
+#[rustfmt::skip]
+const DMG_READ_MASK: [u8; 0x80] = [
+    // P1    SB    SC          DIV   TIMA  TMA   TAC                                         IF
+    0xC0, 0x00, 0x7E, 0xFF, 0x00, 0x00, 0x00, 0xF8, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xE0,
+    // NR10  NR11  NR12  NR13  NR14        NR21  NR22  NR23  NR24  NR30  NR31  NR32  NR33  NR34
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, 0xFF, 0x3F, 0x00, 0xFF, 0xBF, 0x7F, 0xFF, 0x9F, 0xFF, 0xBF, 0xFF,
+    // NR41  NR42  NR43  NR44  NR50  NR51  NR52
+    0xFF, 0x00, 0x00, 0xBF, 0x00, 0x00, 0x70, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    // Wave RAM
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // LCDC  STAT  SCY   SCX   LY    LYC   DMA   BGP   OBP0  OBP1  WY    WX
+    0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+pub(super) fn read_mask(model: Model, offset: usize) -> u8 {
+    if !model.is_cgb() {
+        return DMG_READ_MASK[offset];
+    }
+    // The CGB adds the serial clock speed bit to SC, then KEY1, VBK, RP, the palette
+    // registers, OPRI, SVBK and the undocumented FF72-FF75
+    match offset {
+        0x02 => 0x7C,
+        0x4D => 0x7E,
+        0x4F | 0x6C => 0xFE,
+        0x56 => 0x3C,
+        0x68 | 0x6A => 0x40,
+        0x69 | 0x6B | 0x72..=0x74 => 0x00,
+        0x70 => 0xF8,
+        0x75 => 0x8F,
+        _ => DMG_READ_MASK[offset],
+    }
+}
+
+// Bits the CPU can change, the rest are only set by the hardware
+pub(super) fn write_mask(offset: usize) -> u8 {
+    match offset {
+        0x26 => 0x80, // NR52, the channel status bits are read-only
+        0x41 => 0xF8, // STAT, the mode and coincidence bits
+        0x44 => 0x00, // LY
+        0x4D => 0x01, // KEY1, the current speed bit
+        0x56 => 0xC1, // RP, the received signal bit
+        _ => 0xFF,
+    }
+}
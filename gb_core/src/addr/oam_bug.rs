@@ -0,0 +1,58 @@
+// How the CPU used the address bus while it pointed into OAM during the OAM scan
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OamCorruption {
+    Read,
+    Write,
+    // A read while the pointer register is incremented or decremented, e.g. LD A,(HL+) or POP
+    ReadIncDec,
+}
+
+// OAM is read as 20 rows of 8 bytes, split into four 16-bit words. The corrupted row mixes its
+// first word with the row before it, then takes the other three words from that row. The first
+// row is never corrupted
+pub(super) fn corrupt(oam: &mut [u8; 0xA0], row: usize, corruption: OamCorruption) {
+    if row == 0 || row >= 20 {
+        return;
+    }
+    match corruption {
+        OamCorruption::Read | OamCorruption::Write => {
+            let a = word(oam, row, 0);
+            let b = word(oam, row - 1, 0);
+            let c = word(oam, row - 1, 2);
+            let first = if corruption == OamCorruption::Write {
+                ((a ^ c) & (b ^ c)) ^ c
+            } else {
+                b | (a & c)
+            };
+            set_word(oam, row, first);
+            copy_tail(oam, row - 1, row);
+        }
+        OamCorruption::ReadIncDec => {
+            // The row before is corrupted first and copied over its neighbours, except near the
+            // start and at the end of OAM
+            if (4..19).contains(&row) {
+                let a = word(oam, row - 2, 0);
+                let b = word(oam, row - 1, 0);
+                let c = word(oam, row, 0);
+                let d = word(oam, row - 1, 2);
+                set_word(oam, row - 1, (b & (a | c | d)) | (a & c & d));
+                oam.copy_within((row - 1) * 8..row * 8, row * 8);
+                oam.copy_within((row - 1) * 8..row * 8, (row - 2) * 8);
+            }
+            corrupt(oam, row, OamCorruption::Read);
+        }
+    }
+}
+
+fn word(oam: &[u8; 0xA0], row: usize, index: usize) -> u16 {
+    let i = row * 8 + index * 2;
+    u16::from_le_bytes([oam[i], oam[i + 1]])
+}
+
+fn set_word(oam: &mut [u8; 0xA0], row: usize, value: u16) {
+    oam[row * 8..row * 8 + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn copy_tail(oam: &mut [u8; 0xA0], from: usize, to: usize) {
+    oam.copy_within(from * 8 + 2..from * 8 + 8, to * 8 + 2);
+}
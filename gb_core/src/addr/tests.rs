@@ -0,0 +1,277 @@
+use super::*;
+
+#[test]
+fn test_oam_dma() {
+    let mut addr_space = AddrSpace::empty();
+    addr_space.set_stat(0x80);
+    for i in 0..0xA0 {
+        addr_space.write(0xC000 + i, i as u8 + 1);
+    }
+    addr_space.write(0xFF80, 0x42);
+
+    addr_space.cpu_write(0xFF46, 0xC0);
+    // The transfer starts one M-cycle after the write
+    assert_eq!(addr_space.cpu_read(0xFE00), 0x00);
+
+    addr_space.tick_dma(81);
+    assert_eq!(addr_space.cpu_read(0xFE00), 0xFF);
+    assert_eq!(addr_space.cpu_read(0xFF80), 0x42);
+    // Work RAM shares the bus with the source, so the CPU sees the byte being copied
+    assert_eq!(addr_space.cpu_read(0xD000), 80);
+    // VRAM is on the other bus and still readable
+    assert_eq!(addr_space.cpu_read(0x8000), 0x00);
+
+    addr_space.tick_dma(79);
+    assert_eq!(addr_space.cpu_read(0xFE00), 0xFF);
+    addr_space.tick_dma(1);
+    assert_eq!(addr_space.cpu_read(0xFE00), 1);
+    assert_eq!(addr_space.cpu_read(0xFE9F), 0xA0);
+    assert_eq!(addr_space.cpu_read(0xD000), 0x00);
+}
+
+#[test]
+fn test_ppu_mode_access_restrictions() {
+    let mut addr_space = AddrSpace::empty();
+    addr_space.write(0x8000, 0x12);
+    addr_space.write(0xFE00, 0x34);
+
+    addr_space.set_stat(0x82);
+    assert_eq!(addr_space.cpu_read(0x8000), 0x12);
+    assert_eq!(addr_space.cpu_read(0xFE00), 0xFF);
+
+    addr_space.set_stat(0x83);
+    assert_eq!(addr_space.cpu_read(0x8000), 0xFF);
+    addr_space.cpu_write(0x8000, 0x56);
+    assert_eq!(addr_space.read(0x8000), 0x12);
+
+    addr_space.set_access_restrictions(false);
+    assert_eq!(addr_space.cpu_read(0xFE00), 0x34);
+    addr_space.cpu_write(0x8000, 0x56);
+    assert_eq!(addr_space.read(0x8000), 0x56);
+}
+
+#[test]
+fn test_joypad_register() {
+    let mut addr_space = AddrSpace::empty();
+    addr_space.write(consts::IF_ADDR, 0x00);
+
+    // Nothing selected: all lines high, only the select bits are writable
+    addr_space.write(consts::JOYPAD_ADDR, 0x30);
+    addr_space.set_joypad_buttons(0x11); // Right and A
+    assert_eq!(addr_space.read(consts::JOYPAD_ADDR), 0xFF);
+    assert!(!addr_space.if_joypad());
+
+    // Selecting the directions pulls P10 low and requests the interrupt
+    addr_space.write(consts::JOYPAD_ADDR, 0xE0);
+    assert_eq!(addr_space.read(consts::JOYPAD_ADDR), 0xEE);
+    assert!(addr_space.if_joypad());
+
+    // Both groups selected
+    addr_space.write(consts::IF_ADDR, 0x00);
+    addr_space.set_joypad_buttons(0x21); // Right and B
+    addr_space.write(consts::JOYPAD_ADDR, 0x00);
+    assert_eq!(addr_space.read(consts::JOYPAD_ADDR), 0xCC);
+    assert!(addr_space.if_joypad());
+
+    // Releasing doesn't request it
+    addr_space.write(consts::IF_ADDR, 0x00);
+    addr_space.set_joypad_buttons(0x00);
+    assert_eq!(addr_space.read(consts::JOYPAD_ADDR), 0xCF);
+    assert!(!addr_space.if_joypad());
+}
+
+#[test]
+fn test_boot_rom_mapping() {
+    let cart = vec![0x55; 0x8000];
+    assert_eq!(
+        AddrSpace::with_boot_rom(Model::CGB, vec![0xAA; 0x100], Some(cart.clone())).err(),
+        Some(BootRomError::WrongSize {
+            model: Model::CGB,
+            expected: 0x900,
+            found: 0x100,
+        })
+    );
+
+    // The CGB boot ROM leaves the cartridge header visible
+    let mut addr_space =
+        AddrSpace::with_boot_rom(Model::CGB, vec![0xAA; 0x900], Some(cart)).unwrap();
+    assert_eq!(addr_space.read(0x0000), 0xAA);
+    assert_eq!(addr_space.read(0x0150), 0x55);
+    assert_eq!(addr_space.read(0x08FF), 0xAA);
+    assert_eq!(addr_space.read(0x0900), 0x55);
+
+    addr_space.write(consts::BOOT_ROM_ADDR, 0x00);
+    assert_eq!(addr_space.read(0x0000), 0xAA);
+    addr_space.write(consts::BOOT_ROM_ADDR, 0x11);
+    assert_eq!(addr_space.read(0x0000), 0x55);
+    assert_eq!(addr_space.read(0x0200), 0x55);
+
+    addr_space.reset();
+    assert_eq!(addr_space.read(0x0000), 0xAA);
+}
+
+#[test]
+fn test_io_read_masks() {
+    let mut addr_space = AddrSpace::new([0; 0x100], None);
+    // Unused bits read as 1
+    addr_space.write(consts::IF_ADDR, 0x01);
+    assert_eq!(addr_space.read(consts::IF_ADDR), 0xE1);
+    addr_space.write(consts::TAC_ADDR, 0x05);
+    assert_eq!(addr_space.read(consts::TAC_ADDR), 0xFD);
+    // Write-only and unmapped registers read 0xFF
+    addr_space.write(0xFF13, 0x12);
+    assert_eq!(addr_space.read(0xFF13), 0xFF);
+    addr_space.write(0xFF03, 0x00);
+    assert_eq!(addr_space.read(0xFF03), 0xFF);
+    assert_eq!(addr_space.read(0xFF4D), 0xFF);
+    addr_space.write(0xFF30, 0x00);
+    assert_eq!(addr_space.read(0xFF30), 0x00);
+
+    assert_eq!(addr_space.read(0xFEA0), 0x00);
+    addr_space.write(0xFEA0, 0x12);
+    assert_eq!(addr_space.read(0xFEA0), 0x00);
+    let addr_space = AddrSpace::post_boot(Model::CGB, None);
+    assert_eq!(addr_space.read(0xFEB4), 0xBB);
+    assert_eq!(addr_space.read(0xFF4F), 0xFE);
+}
+
+#[test]
+fn test_io_write_masks() {
+    let mut addr_space = AddrSpace::new([0; 0x100], None);
+    // LY is only moved by the PPU
+    addr_space.set_ly(0x42);
+    addr_space.write(consts::LY_ADDR, 0x00);
+    assert_eq!(addr_space.read(consts::LY_ADDR), 0x42);
+    // The channel status bits of NR52 can't be written
+    addr_space.write(0xFF26, 0x8F);
+    assert_eq!(addr_space.read(0xFF26), 0xF0);
+    // Neither can the mode and coincidence bits of STAT
+    addr_space.set_stat(0x85);
+    addr_space.write(consts::LCD_STAT_ADDR, 0x7A);
+    assert_eq!(addr_space.read(consts::LCD_STAT_ADDR), 0xFD);
+}
+
+#[test]
+fn test_oam_corruption() {
+    use crate::bus::{Bus, SystemBus};
+    use crate::ppu::PPU;
+
+    let rows = [
+        [0x0F, 0x0F, 0xAA, 0xAA, 0xFF, 0x00, 0x55, 0x55],
+        [0x33, 0x33, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06],
+    ];
+    for model in [Model::DMG, Model::CGB] {
+        let mut addr_space = AddrSpace::post_boot(model, None);
+        let mut ppu = PPU::post_boot();
+        for (i, &byte) in rows.concat().iter().enumerate() {
+            addr_space.write(0xFE00 + i as u16, byte);
+        }
+        let mut bus = SystemBus::new(&mut addr_space, &mut ppu);
+        // Run to the start of the OAM scan of line 0
+        for _ in 0..14 {
+            bus.tick();
+        }
+        // INC HL with HL in OAM while the PPU reads row 1
+        bus.tick_inc_dec(0xFE10);
+        drop(bus);
+
+        let row1: Vec<u8> = (0xFE08..0xFE10).map(|addr| addr_space.read(addr)).collect();
+        if model == Model::DMG {
+            assert_eq!(row1, [0x3F, 0x03, 0xAA, 0xAA, 0xFF, 0x00, 0x55, 0x55]);
+        } else {
+            assert_eq!(row1, rows[1]);
+        }
+    }
+
+    // Turning the access restrictions off doesn't hide the bug
+    let mut addr_space = AddrSpace::post_boot(Model::DMG, None);
+    addr_space.set_access_restrictions(false);
+    addr_space.write(0xFE02, 0x0F);
+    addr_space.corrupt_oam(1, OamCorruption::Write);
+    assert_eq!(addr_space.read(0xFE0A), 0x0F);
+}
+
+// Counts M-cycles in FF05 and requests the timer interrupt when it overflows
+struct CounterDevice {
+    counter: u8,
+    overflowed: bool,
+}
+
+impl BusDevice for CounterDevice {
+    fn read(&self, _addr: u16) -> u8 {
+        self.counter
+    }
+
+    fn write(&mut self, _addr: u16, data: u8) {
+        self.counter = data;
+    }
+
+    fn tick(&mut self, m_cycles: u64) {
+        let counter = self.counter as u64 + m_cycles;
+        self.counter = counter as u8;
+        self.overflowed |= counter > 0xFF;
+    }
+
+    fn next_event(&self) -> Option<u64> {
+        Some(0x100 - self.counter as u64)
+    }
+
+    fn take_interrupts(&mut self) -> u8 {
+        if std::mem::replace(&mut self.overflowed, false) {
+            0x04
+        } else {
+            0x00
+        }
+    }
+}
+
+#[test]
+fn test_bus_devices() {
+    let mut addr_space = AddrSpace::new([0; 0x100], None);
+    addr_space.write(consts::IF_ADDR, 0x00);
+    addr_space.map_device(
+        &[consts::TIMA_ADDR..=consts::TIMA_ADDR],
+        Box::new(CounterDevice {
+            counter: 0,
+            overflowed: false,
+        }),
+    );
+
+    addr_space.cpu_write(consts::TIMA_ADDR, 0xF0);
+    assert_eq!(addr_space.cpu_read(consts::TIMA_ADDR), 0xF0);
+    // The neighbouring registers are still handled by the address space
+    addr_space.write(consts::TAC_ADDR, 0x05);
+    assert_eq!(addr_space.read(consts::TAC_ADDR), 0xFD);
+
+    // The device is only run when the CPU reads it...
+    addr_space.scheduler().advance(4 * 4);
+    assert_eq!(addr_space.read(consts::TIMA_ADDR), 0xF0);
+    assert_eq!(addr_space.cpu_read(consts::TIMA_ADDR), 0xF4);
+    // ...or when the overflow it reported is due
+    addr_space.scheduler().advance(11 * 4);
+    assert!(!addr_space.scheduler().is_due(Peripheral::Devices));
+    addr_space.scheduler().advance(4);
+    assert!(addr_space.scheduler().is_due(Peripheral::Devices));
+    addr_space.sync_devices();
+    assert_eq!(addr_space.read(consts::TIMA_ADDR), 0x00);
+    assert!(addr_space.if_timer());
+}
+
+#[test]
+fn test_serial_port() {
+    let mut addr_space = AddrSpace::post_boot(Model::DMG, None);
+    addr_space.log_serial(true);
+    addr_space.write(consts::SB_ADDR, b'A');
+    addr_space.write(consts::SC_ADDR, 0x81);
+    assert_eq!(addr_space.serial_log(), b"A");
+
+    addr_space.scheduler().advance(1023 * 4);
+    assert_eq!(addr_space.read(consts::SC_ADDR), 0xFF);
+    addr_space.scheduler().advance(4);
+    // Reads see the end of the transfer before the interrupt is raised on the next sync
+    assert_eq!(addr_space.read(consts::SC_ADDR), 0x7F);
+    assert!(addr_space.scheduler().is_due(Peripheral::Serial));
+    addr_space.sync_serial();
+    assert!(addr_space.if_serial());
+    assert_eq!(addr_space.read(consts::SB_ADDR), 0xFF);
+}
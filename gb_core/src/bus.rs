@@ -0,0 +1,169 @@
+use super::addr::*;
+use super::consts::*;
+use super::ppu::PPU;
+use super::scheduler::Peripheral;
+#[cfg(test)]
+mod tests;
+
+// The CPU side of the memory bus. Every read and write takes one M-cycle and `tick` is an
+// M-cycle where the CPU doesn't access memory, so implementations advance the rest of the
+// system on each call
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, data: u8);
+    fn tick(&mut self);
+    // An internal M-cycle where the CPU increments or decrements the register pair `addr` and
+    // puts it on the address bus, which on DMG corrupts OAM if it points into it
+    fn tick_inc_dec(&mut self, _addr: u16) {
+        self.tick();
+    }
+    // A read where the pointer register is incremented or decremented in the same M-cycle
+    fn read_inc_dec(&mut self, addr: u16) -> u8 {
+        self.read(addr)
+    }
+    // Interrupts both requested in IF and enabled in IE
+    fn pending_interrupts(&self) -> u8;
+    // Clears the IF bit of the interrupt being serviced
+    fn acknowledge_interrupt(&mut self, bit: u8);
+    // Joypad input lines P10-P13, low while a selected input is pressed
+    fn joypad_lines(&self) -> u8;
+    // STOP resets DIV and the timer stands still until the CPU wakes up, `false` restarts it
+    fn stop_timer(&mut self, _stopped: bool) {}
+}
+
+// Connects the CPU to the address space, the PPU and the OAM DMA. The PPU, the timer, the DMA
+// and the devices only run when their next event is due or when they have to be up to date for
+// a CPU access. Frontends keep one bus for as long as they run the CPU, see GameBoy::run_frame
+pub struct SystemBus<'a> {
+    pub addr_space: &'a mut AddrSpace,
+    pub ppu: &'a mut PPU,
+    // M-cycles elapsed since the bus was created
+    pub cycles: u32,
+    // Set once the PPU has a frame ready
+    pub vblank: bool,
+}
+
+impl<'a> SystemBus<'a> {
+    pub fn new(addr_space: &'a mut AddrSpace, ppu: &'a mut PPU) -> Self {
+        let mut bus = SystemBus {
+            addr_space,
+            ppu,
+            cycles: 0,
+            vblank: false,
+        };
+        bus.addr_space.scheduler().catch_up(Peripheral::Ppu);
+        bus.schedule_ppu();
+        bus
+    }
+
+    // Runs the PPU for the dots it missed. The OAM DMA goes first so the PPU sees what it copied
+    fn sync_ppu(&mut self) {
+        self.addr_space.sync_dma();
+        let dots = self.addr_space.scheduler().catch_up(Peripheral::Ppu);
+        if self.ppu.tick(dots as u32, self.addr_space) {
+            self.vblank = true;
+        }
+        self.schedule_ppu();
+    }
+
+    fn schedule_ppu(&mut self) {
+        let dots = self.ppu.dots_to_next_event(self.addr_space);
+        self.addr_space
+            .scheduler()
+            .schedule(Peripheral::Ppu, dots as u64);
+    }
+
+    // Putting an OAM address on the bus while the PPU scans OAM corrupts the row it's reading
+    fn oam_bug(&mut self, addr: u16, corruption: OamCorruption) {
+        if (0xFE00..=0xFEFF).contains(&addr) {
+            self.sync_ppu();
+            if let Some(row) = self.ppu.oam_row(self.addr_space) {
+                self.addr_space.corrupt_oam(row, corruption);
+            }
+        }
+    }
+}
+
+impl Bus for SystemBus<'_> {
+    // The access happens at the end of the M-cycle, after the PPU and DMA have moved
+    fn read(&mut self, addr: u16) -> u8 {
+        self.tick();
+        self.oam_bug(addr, OamCorruption::Read);
+        self.addr_space.cpu_read(addr)
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.tick();
+        self.oam_bug(addr, OamCorruption::Write);
+        // The PPU has to reach the write before it sees the new register value
+        let lcd_register = (LCDC_ADDR..=WX_ADDR).contains(&addr);
+        if lcd_register {
+            self.sync_ppu();
+        }
+        self.addr_space.cpu_write(addr, data);
+        if lcd_register {
+            self.schedule_ppu();
+        }
+    }
+
+    fn tick_inc_dec(&mut self, addr: u16) {
+        self.tick();
+        self.oam_bug(addr, OamCorruption::Write);
+    }
+
+    fn read_inc_dec(&mut self, addr: u16) -> u8 {
+        self.tick();
+        self.oam_bug(addr, OamCorruption::ReadIncDec);
+        self.addr_space.cpu_read(addr)
+    }
+
+    fn tick(&mut self) {
+        self.cycles += 1;
+        let scheduler = self.addr_space.scheduler();
+        scheduler.advance(4);
+        if !scheduler.any_due() {
+            return;
+        }
+        if scheduler.is_due(Peripheral::Dma) {
+            self.addr_space.sync_dma();
+        }
+        if self.addr_space.scheduler().is_due(Peripheral::Ppu) {
+            self.sync_ppu();
+        }
+        if self.addr_space.scheduler().is_due(Peripheral::Timer) {
+            self.addr_space.sync_timer();
+        }
+        if self.addr_space.scheduler().is_due(Peripheral::Serial) {
+            self.addr_space.sync_serial();
+        }
+        if self.addr_space.scheduler().is_due(Peripheral::Devices) {
+            self.addr_space.sync_devices();
+        }
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.addr_space.read(IE_ADDR) & self.addr_space.read(IF_ADDR) & 0x1F
+    }
+
+    fn acknowledge_interrupt(&mut self, bit: u8) {
+        let if_value = self.addr_space.read(IF_ADDR) & !(1 << bit);
+        self.addr_space.write(IF_ADDR, if_value);
+    }
+
+    fn joypad_lines(&self) -> u8 {
+        self.addr_space.read(JOYPAD_ADDR) & 0x0F
+    }
+
+    fn stop_timer(&mut self, stopped: bool) {
+        self.addr_space.stop_timer(stopped);
+    }
+}
+
+// Whoever looks at the PPU, OAM or the devices after the bus is gone sees them up to date.
+// Events are never left pending, so this can't finish a frame
+impl Drop for SystemBus<'_> {
+    fn drop(&mut self) {
+        self.sync_ppu();
+        self.addr_space.sync_devices();
+    }
+}
@@ -0,0 +1,148 @@
+use super::*;
+use crate::cpu::CPU;
+use crate::instructions::step;
+use crate::model::Model;
+
+// Ticks the PPU on every M-cycle, like SystemBus did before the scheduler
+struct EagerBus<'a> {
+    addr_space: &'a mut AddrSpace,
+    ppu: &'a mut PPU,
+}
+
+impl Bus for EagerBus<'_> {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.tick();
+        self.addr_space.cpu_read(addr)
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.tick();
+        self.addr_space.cpu_write(addr, data);
+    }
+
+    fn tick(&mut self) {
+        self.ppu.tick(4, self.addr_space);
+        self.addr_space.tick_dma(1);
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.addr_space.read(IE_ADDR) & self.addr_space.read(IF_ADDR) & 0x1F
+    }
+
+    fn acknowledge_interrupt(&mut self, bit: u8) {
+        let if_value = self.addr_space.read(IF_ADDR) & !(1 << bit);
+        self.addr_space.write(IF_ADDR, if_value);
+    }
+
+    fn joypad_lines(&self) -> u8 {
+        self.addr_space.read(JOYPAD_ADDR) & 0x0F
+    }
+}
+
+#[test]
+fn test_scheduled_ppu_matches_eager_ppu() {
+    // Keeps moving LYC to LY with the LYC and mode 0 STAT sources enabled, and samples IF
+    let program = [
+        0x3E, 0x48, // LD A,0x48
+        0xE0, 0x41, // LDH (STAT),A
+        0xF0, 0x44, // LDH A,(LY)
+        0xE0, 0x45, // LDH (LYC),A
+        0xF0, 0x0F, // LDH A,(IF)
+        0x47, // LD B,A
+        0xAF, // XOR A
+        0xE0, 0x0F, // LDH (IF),A
+        0x18, 0xF4, // JR -12
+    ];
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+
+    let mut lazy = (
+        Model::DMG.post_boot_cpu(0),
+        AddrSpace::post_boot(Model::DMG, Some(rom.clone())),
+        PPU::post_boot(),
+    );
+    let mut eager = (
+        Model::DMG.post_boot_cpu(0),
+        AddrSpace::post_boot(Model::DMG, Some(rom)),
+        PPU::post_boot(),
+    );
+
+    let state = |(cpu, addr_space, _): &(CPU, AddrSpace, PPU)| {
+        (
+            cpu.pc,
+            cpu.a,
+            cpu.b,
+            addr_space.read(LY_ADDR),
+            addr_space.read(LCD_STAT_ADDR),
+            addr_space.read(IF_ADDR),
+        )
+    };
+    let mut frames = 0;
+    while frames < 3 {
+        let mut bus = SystemBus::new(&mut lazy.1, &mut lazy.2);
+        step(&mut lazy.0, &mut bus);
+        if bus.vblank {
+            frames += 1;
+        }
+        drop(bus);
+        let mut bus = EagerBus {
+            addr_space: &mut eager.1,
+            ppu: &mut eager.2,
+        };
+        step(&mut eager.0, &mut bus);
+
+        assert_eq!(state(&lazy), state(&eager));
+    }
+}
+
+#[test]
+fn test_dma_finishes_without_cpu_accesses() {
+    let mut addr_space = AddrSpace::post_boot(Model::DMG, None);
+    let mut ppu = PPU::post_boot();
+    addr_space.write(0xC000, 0x42);
+    addr_space.write(0xC09F, 0x43);
+
+    let mut bus = SystemBus::new(&mut addr_space, &mut ppu);
+    bus.write(0xFF46, 0xC0);
+    // Like a halted CPU: internal M-cycles only, the completion event brings the copy up to date
+    for _ in 0..161 {
+        bus.tick();
+    }
+    assert_eq!(bus.addr_space.read(0xFE00), 0x42);
+    assert_eq!(bus.addr_space.read(0xFE9F), 0x43);
+}
+
+#[test]
+fn test_stop_resets_and_holds_div() {
+    let program = [
+        0x10, // STOP
+        0xF0, 0x04, // LDH A,(DIV)
+        0x18, 0xFC, // JR -4
+    ];
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+    let mut cpu = Model::DMG.post_boot_cpu(0);
+    let mut addr_space = AddrSpace::post_boot(Model::DMG, Some(rom));
+    let mut ppu = PPU::post_boot();
+    assert_ne!(addr_space.read(DIV_ADDR), 0x00);
+    // Directions selected, so pressing Right wakes the CPU up
+    addr_space.write(JOYPAD_ADDR, 0x20);
+
+    let mut bus = SystemBus::new(&mut addr_space, &mut ppu);
+    step(&mut cpu, &mut bus);
+    assert!(cpu.stopped);
+    for _ in 0..1000 {
+        step(&mut cpu, &mut bus);
+    }
+    assert!(cpu.stopped);
+
+    // DIV counts from 0 again once the CPU is woken up, as if no time had passed
+    bus.addr_space.set_joypad_buttons(0x01);
+    step(&mut cpu, &mut bus);
+    assert!(!cpu.stopped);
+    assert_eq!(cpu.a, 0x00);
+    for _ in 0..64 {
+        step(&mut cpu, &mut bus);
+    }
+    assert_ne!(cpu.a, 0x00);
+}
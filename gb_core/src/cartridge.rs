@@ -0,0 +1,82 @@
+use super::device::BusDevice;
+use super::save_state::{SaveStateError, StateReader, StateWriter};
+#[cfg(test)]
+mod tests;
+
+// Cartridge ROM and external RAM, mapped at 0000-7FFF and A000-BFFF
+pub struct Cartridge {
+    rom: Vec<u8>,
+    ram: [u8; 0x2000],
+    rom_bank: u8,
+    memory_model: u8,
+}
+
+impl Cartridge {
+    pub fn new(mut rom: Vec<u8>) -> Self {
+        // Anything smaller than the two fixed banks reads as open bus
+        if rom.len() < 0x8000 {
+            rom.resize(0x8000, 0xFF);
+        }
+        Cartridge {
+            rom,
+            ram: [0; 0x2000],
+            rom_bank: 1,
+            memory_model: 0,
+        }
+    }
+
+    fn cartridge_type(&self) -> u8 {
+        self.rom[0x147]
+    }
+
+    // Offset in the ROM of the bank switched into 4000-7FFF
+    fn bank_offset(&self) -> usize {
+        let banks = self.rom.len() / 0x4000;
+        (self.rom_bank as usize % banks) * 0x4000
+    }
+}
+
+impl BusDevice for Cartridge {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0..=0x3FFF => self.rom[addr as usize],
+            0x4000..=0x7FFF => self.rom[self.bank_offset() + (addr - 0x4000) as usize],
+            0xA000..=0xBFFF => self.ram[(addr - 0xA000) as usize],
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            //TODO: cover all the cartridge types and memory models :(
+            0x6000..=0x7FFF if self.cartridge_type() == 1 => {
+                self.memory_model = 0x1 & data;
+            }
+            0x2000..=0x3FFF if self.cartridge_type() == 1 => {
+                self.rom_bank = 0x1.max(0x1F & data);
+            }
+            0xA000..=0xBFFF => self.ram[(addr - 0xA000) as usize] = data,
+            _ => {}
+        }
+    }
+
+    fn reset(&mut self) {
+        self.ram = [0; 0x2000];
+        self.rom_bank = 1;
+        self.memory_model = 0;
+    }
+
+    // The ROM itself is identified by the hash in the state header
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.ram);
+        state.write_u8(self.rom_bank);
+        state.write_u8(self.memory_model);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        state.read_bytes(&mut self.ram)?;
+        self.rom_bank = state.read_u8()?;
+        self.memory_model = state.read_u8()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,25 @@
+use super::*;
+
+#[test]
+fn test_mbc1_rom_banks() {
+    let mut rom = vec![0; 0x10000];
+    rom[0x147] = 1;
+    for bank in 0..4 {
+        rom[bank * 0x4000 + 0x100] = bank as u8;
+    }
+    let mut cartridge = Cartridge::new(rom);
+    assert_eq!(cartridge.read(0x4100), 1);
+
+    cartridge.write(0x2000, 3);
+    assert_eq!(cartridge.read(0x4100), 3);
+    assert_eq!(cartridge.read(0x0100), 0);
+    // Bank 0 can't be switched in, it selects bank 1
+    cartridge.write(0x2000, 0);
+    assert_eq!(cartridge.read(0x4100), 1);
+
+    // ROM can't be written
+    cartridge.write(0x4100, 0x42);
+    assert_eq!(cartridge.read(0x4100), 1);
+    cartridge.write(0xA000, 0x42);
+    assert_eq!(cartridge.read(0xA000), 0x42);
+}
@@ -12,11 +12,16 @@ pub const LYC_ADDR: u16 = 0xFF45;
 pub const BG_PALETTE_ADDR: u16 = 0xFF47;
 pub const OBJ0_PALETTE_ADDR: u16 = 0xFF48;
 pub const OBJ1_PALETTE_ADDR: u16 = 0xFF49;
+pub const SB_ADDR: u16 = 0xFF01;
+pub const SC_ADDR: u16 = 0xFF02;
+pub const DIV_ADDR: u16 = 0xFF04;
 pub const TIMA_ADDR: u16 = 0xff05;
+pub const TMA_ADDR: u16 = 0xFF06;
 pub const TAC_ADDR: u16 = 0xff07;
 pub const IE_ADDR: u16 = 0xFFFF;
 pub const IF_ADDR: u16 = 0xFF0F;
 pub const JOYPAD_ADDR: u16 = 0xFF00;
+pub const BOOT_ROM_ADDR: u16 = 0xFF50;
 pub const TILE_MAP_ADDR: u16 = 0x9800;
 pub const TILE_MAP_ADDR_2: u16 = 0x9C00;
 
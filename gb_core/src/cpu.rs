@@ -1,4 +1,5 @@
-use super::addr::*;
+use super::bus::*;
+use super::save_state::{SaveStateError, StateReader, StateWriter};
 use std::fmt;
 
 #[derive(Clone, Copy)]
@@ -23,6 +24,13 @@ pub enum Register16 {
     PC,
 }
 
+// Reported by the CPU for the frontend to act on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    // An undefined opcode was executed, nothing else runs until the CPU is reset
+    Locked { pc: u16, opcode: u8 },
+}
+
 #[derive(Clone, Copy)]
 pub struct CPU {
     pub a: u8,
@@ -37,6 +45,12 @@ pub struct CPU {
     pub pc: u16,
     pub ime: bool,
     pub schedule_ime: bool,
+    pub halted: bool,
+    pub stopped: bool,
+    pub locked: bool,
+    pub event: Option<Event>,
+    // Set by HALT when it fails to halt with IME clear, the next opcode fetch won't advance PC
+    pub halt_bug: bool,
 }
 
 impl CPU {
@@ -120,9 +134,13 @@ impl CPU {
     }
 
     // Returns the value in the address space pointed by the PC and increments the PC by 1
-    pub fn next_instr(&mut self, addr_space: &AddrSpace) -> u8 {
-        let instr = addr_space.read(self.pc);
-        self.pc = self.pc.wrapping_add(1);
+    pub fn next_instr(&mut self, bus: &mut impl Bus) -> u8 {
+        let instr = bus.read(self.pc);
+        if self.halt_bug {
+            self.halt_bug = false;
+        } else {
+            self.pc = self.pc.wrapping_add(1);
+        }
         instr
     }
 
@@ -167,6 +185,68 @@ impl CPU {
             | (if c { 0x10 } else { 0 });
     }
 
+    pub fn save_state(&self, state: &mut StateWriter) {
+        for register in [self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l] {
+            state.write_u8(register);
+        }
+        state.write_u16(self.sp);
+        state.write_u16(self.pc);
+        for flag in [
+            self.ime,
+            self.schedule_ime,
+            self.halted,
+            self.stopped,
+            self.locked,
+            self.halt_bug,
+        ] {
+            state.write_bool(flag);
+        }
+        match self.event {
+            None => state.write_u8(0),
+            Some(Event::Locked { pc, opcode }) => {
+                state.write_u8(1);
+                state.write_u16(pc);
+                state.write_u8(opcode);
+            }
+        }
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        for register in [
+            &mut self.a,
+            &mut self.f,
+            &mut self.b,
+            &mut self.c,
+            &mut self.d,
+            &mut self.e,
+            &mut self.h,
+            &mut self.l,
+        ] {
+            *register = state.read_u8()?;
+        }
+        self.sp = state.read_u16()?;
+        self.pc = state.read_u16()?;
+        for flag in [
+            &mut self.ime,
+            &mut self.schedule_ime,
+            &mut self.halted,
+            &mut self.stopped,
+            &mut self.locked,
+            &mut self.halt_bug,
+        ] {
+            *flag = state.read_bool()?;
+        }
+        self.event = match state.read_u8()? {
+            0 => None,
+            1 => Some(Event::Locked {
+                pc: state.read_u16()?,
+                opcode: state.read_u8()?,
+            }),
+            _ => return Err(SaveStateError::Corrupted),
+        };
+        Ok(())
+    }
+
     // Initializer for DMG mode
 
     pub fn new() -> CPU {
@@ -183,6 +263,11 @@ impl CPU {
             pc: 0x0000,
             ime: false,
             schedule_ime: false,
+            halted: false,
+            stopped: false,
+            locked: false,
+            event: None,
+            halt_bug: false,
         }
     }
 }
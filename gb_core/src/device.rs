@@ -0,0 +1,134 @@
+use super::save_state::{SaveStateError, StateReader, StateWriter};
+use std::ops::RangeInclusive;
+
+// Hardware answering CPU accesses on part of the address space: a cartridge and its mapper, a
+// timer, a test fixture... Addresses are passed as the CPU sees them, not relative to the range
+pub trait BusDevice {
+    fn read(&self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, data: u8);
+    // Runs the device for the M-cycles elapsed since the last call. Devices are only run when
+    // the CPU accesses them or when the event they report through next_event is due
+    fn tick(&mut self, _m_cycles: u64) {}
+    // M-cycles until the device has to run, e.g. to request an interrupt. None if nothing
+    // happens until the CPU accesses it
+    fn next_event(&self) -> Option<u64> {
+        None
+    }
+    // Interrupts requested since the last call, in IF bit order
+    fn take_interrupts(&mut self) -> u8 {
+        0
+    }
+    // Called when the console is reset
+    fn reset(&mut self) {}
+    // Devices with state of their own (RAM, bank registers, counters...) add it to save states.
+    // load_state has to read exactly what save_state wrote
+    fn save_state(&self, _state: &mut StateWriter) {}
+    fn load_state(&mut self, _state: &mut StateReader) -> Result<(), SaveStateError> {
+        Ok(())
+    }
+}
+
+// Routes address ranges to devices. A device can be mapped to several ranges, single IO
+// registers are one-address ranges. Ranges mapped later take precedence over earlier ones
+pub struct Devices {
+    devices: Vec<Box<dyn BusDevice>>,
+    ranges: Vec<(RangeInclusive<u16>, usize)>,
+    // The 256-byte pages a range touches, so most accesses skip the search
+    mapped_pages: [bool; 0x100],
+}
+
+impl Default for Devices {
+    fn default() -> Self {
+        Devices::new()
+    }
+}
+
+impl Devices {
+    pub fn new() -> Self {
+        Devices {
+            devices: Vec::new(),
+            ranges: Vec::new(),
+            mapped_pages: [false; 0x100],
+        }
+    }
+
+    pub fn map(&mut self, ranges: &[RangeInclusive<u16>], device: Box<dyn BusDevice>) {
+        let index = self.devices.len();
+        self.devices.push(device);
+        for range in ranges {
+            for page in (range.start() >> 8)..=(range.end() >> 8) {
+                self.mapped_pages[page as usize] = true;
+            }
+            self.ranges.push((range.clone(), index));
+        }
+    }
+
+    fn find(&self, addr: u16) -> Option<usize> {
+        if !self.mapped_pages[(addr >> 8) as usize] {
+            return None;
+        }
+        self.ranges
+            .iter()
+            .rev()
+            .find(|(range, _)| range.contains(&addr))
+            .map(|&(_, index)| index)
+    }
+
+    // None if no device is mapped at the address
+    pub fn read(&self, addr: u16) -> Option<u8> {
+        self.find(addr).map(|index| self.devices[index].read(addr))
+    }
+
+    // Returns false if no device is mapped at the address
+    pub fn write(&mut self, addr: u16, data: u8) -> bool {
+        match self.find(addr) {
+            Some(index) => {
+                self.devices[index].write(addr, data);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // True if a device answers at the address
+    pub fn maps(&self, addr: u16) -> bool {
+        self.find(addr).is_some()
+    }
+
+    // Runs every device for the given M-cycles and returns the interrupts they requested
+    pub fn tick(&mut self, m_cycles: u64) -> u8 {
+        let mut interrupts = 0;
+        for device in &mut self.devices {
+            if m_cycles > 0 {
+                device.tick(m_cycles);
+            }
+            interrupts |= device.take_interrupts();
+        }
+        interrupts
+    }
+
+    // The soonest event of any device
+    pub fn next_event(&self) -> Option<u64> {
+        self.devices.iter().filter_map(|d| d.next_event()).min()
+    }
+
+    pub fn reset(&mut self) {
+        for device in &mut self.devices {
+            device.reset();
+        }
+    }
+
+    // Devices are saved in the order they were mapped, which is the same for the same ROM
+    pub fn save_state(&self, state: &mut StateWriter) {
+        for device in &self.devices {
+            device.save_state(state);
+        }
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        for device in &mut self.devices {
+            device.load_state(state)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+use super::save_state::{SaveStateError, StateReader, StateWriter};
+
+// OAM DMA started by a write to FF46. It copies one byte per M-cycle from the source page
+// into OAM, taking 160 M-cycles in total
+pub struct Dma {
+    source: u16,
+    index: u16,
+    delay: u8,
+    running: bool,
+    last_byte: u8,
+}
+
+impl Default for Dma {
+    fn default() -> Self {
+        Dma::new()
+    }
+}
+
+impl Dma {
+    pub fn new() -> Self {
+        Dma {
+            source: 0,
+            index: 0,
+            delay: 0,
+            running: false,
+            last_byte: 0xFF,
+        }
+    }
+
+    // Writing FF46 while a transfer is running restarts it from the new page
+    pub fn start(&mut self, page: u8) {
+        self.source = (page as u16) << 8;
+        self.index = 0;
+        self.delay = 1;
+        self.running = true;
+    }
+
+    // True while bytes are being copied and the CPU bus is taken by the DMA
+    pub fn active(&self) -> bool {
+        self.running && self.delay == 0
+    }
+
+    pub fn running(&self) -> bool {
+        self.running
+    }
+
+    // M-cycles until the transfer is over, 0 if none is running
+    pub fn m_cycles_left(&self) -> u64 {
+        if !self.running {
+            return 0;
+        }
+        self.delay as u64 + (0xA0 - self.index) as u64
+    }
+
+    // Address of the next byte to copy, or None if no byte is copied this M-cycle
+    pub fn next_source(&mut self) -> Option<u16> {
+        if !self.running {
+            return None;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return None;
+        }
+        Some(self.source + self.index)
+    }
+
+    // Records the copied byte and returns the OAM offset it goes to
+    pub fn copied(&mut self, byte: u8) -> usize {
+        let offset = self.index as usize;
+        self.last_byte = byte;
+        self.index += 1;
+        if self.index == 0xA0 {
+            self.running = false;
+        }
+        offset
+    }
+
+    pub fn source(&self) -> u16 {
+        self.source
+    }
+
+    // The value the CPU sees when it reads from the bus the DMA is using
+    pub fn last_byte(&self) -> u8 {
+        self.last_byte
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.source);
+        state.write_u16(self.index);
+        state.write_u8(self.delay);
+        state.write_bool(self.running);
+        state.write_u8(self.last_byte);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.source = state.read_u16()?;
+        self.index = state.read_u16()?;
+        self.delay = state.read_u8()?;
+        self.running = state.read_bool()?;
+        self.last_byte = state.read_u8()?;
+        // A running transfer copies to OAM at the index
+        if self.index > 0xA0 || (self.running && self.index == 0xA0) {
+            return Err(SaveStateError::Corrupted);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,312 @@
+use super::addr::{AddrSpace, BootRomError};
+use super::bus::SystemBus;
+use super::consts;
+use super::cpu::CPU;
+use super::instructions;
+use super::joypad::{Button, JoypadState};
+use super::model::Model;
+use super::movie::{Movie, MovieError};
+use super::ppu::{HEIGHT, PPU, WIDTH};
+use super::rewind::Rewind;
+use super::save_state::{self, SaveStateError, StateReader, StateWriter};
+#[cfg(test)]
+mod tests;
+
+// A whole Game Boy: the CPU with the memory, PPU and inputs it's wired to. Frontends drive it
+// with step, run_frame or run_cycles instead of ticking the parts themselves. Nothing depends
+// on the host (time, randomness...), so the same inputs on the same frames always give the
+// same result, see Movie
+pub struct GameBoy {
+    pub cpu: CPU,
+    pub ppu: PPU,
+    pub addr_space: AddrSpace,
+    // What the machine sees
+    joypad: JoypadState,
+    // What the frontend asks for. It goes straight to the joypad, except during movies where it
+    // is only picked up at the start of each frame
+    input: JoypadState,
+    // Set when the last step finished a frame
+    frame_ready: bool,
+    rewind: Option<Rewind>,
+    movie: Option<MovieSession>,
+}
+
+enum MovieSession {
+    Recording(Movie),
+    // With the next frame to play
+    Playing(Movie, usize),
+}
+
+impl GameBoy {
+    // Runs the built-in DMG boot ROM before the cartridge
+    pub fn new(cartridge: Option<Vec<u8>>) -> Self {
+        GameBoy::from_parts(AddrSpace::new(consts::DMG, cartridge))
+    }
+
+    pub fn with_boot_rom(
+        model: Model,
+        boot_rom: Vec<u8>,
+        cartridge: Option<Vec<u8>>,
+    ) -> Result<Self, BootRomError> {
+        Ok(GameBoy::from_parts(AddrSpace::with_boot_rom(
+            model, boot_rom, cartridge,
+        )?))
+    }
+
+    // Skips the boot ROM, starting from the state the model's boot ROM leaves behind
+    pub fn post_boot(model: Model, cartridge: Option<Vec<u8>>) -> Self {
+        GameBoy::from_parts(AddrSpace::post_boot(model, cartridge))
+    }
+
+    fn from_parts(addr_space: AddrSpace) -> Self {
+        let mut gameboy = GameBoy {
+            cpu: CPU::new(),
+            ppu: PPU::new(),
+            addr_space,
+            joypad: JoypadState::new(),
+            input: JoypadState::new(),
+            frame_ready: false,
+            rewind: None,
+            movie: None,
+        };
+        gameboy.reset_cpu_and_ppu();
+        gameboy
+    }
+
+    // Restarts the cartridge the same way it was started the first time. Stops the movie being
+    // recorded or played
+    pub fn reset(&mut self) {
+        self.joypad = JoypadState::new();
+        self.frame_ready = false;
+        self.movie = None;
+        if let Some(rewind) = &mut self.rewind {
+            rewind.clear();
+        }
+        self.addr_space.reset();
+        self.reset_cpu_and_ppu();
+    }
+
+    fn reset_cpu_and_ppu(&mut self) {
+        if self.addr_space.skips_boot() {
+            let model = self.addr_space.model();
+            self.cpu = model.post_boot_cpu(self.addr_space.header_checksum());
+            self.ppu = PPU::post_boot();
+        } else {
+            self.cpu = CPU::new();
+            self.ppu = PPU::new();
+        }
+    }
+
+    // Runs one instruction, or services an interrupt, and returns the M-cycles it took
+    pub fn step(&mut self) -> u32 {
+        self.run_on_bus(1)
+    }
+
+    // Runs whole instructions on one bus for at least the given number of M-cycles, stopping
+    // early at the end of a frame. The bus is dropped before the movie and rewind have a look
+    fn run_on_bus(&mut self, m_cycles: u32) -> u32 {
+        let mut bus = SystemBus::new(&mut self.addr_space, &mut self.ppu);
+        let mut cycles = 0;
+        while cycles < m_cycles && !bus.vblank {
+            cycles += instructions::step(&mut self.cpu, &mut bus);
+        }
+        self.frame_ready = bus.vblank;
+        drop(bus);
+        if self.frame_ready {
+            self.next_movie_frame();
+            self.take_snapshot();
+        }
+        cycles
+    }
+
+    // Keeps a snapshot every `interval` frames for rewind_frame, using at most about `budget`
+    // bytes
+    pub fn enable_rewind(&mut self, interval: u32, budget: usize) {
+        self.rewind = Some(Rewind::new(interval, budget));
+    }
+
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+    }
+
+    fn take_snapshot(&mut self) {
+        let due = match &mut self.rewind {
+            Some(rewind) => rewind.frame_finished(),
+            None => false,
+        };
+        if due {
+            let state = self.save_state();
+            if let Some(rewind) = &mut self.rewind {
+                rewind.push(state);
+            }
+        }
+    }
+
+    // Goes back one snapshot, `interval` frames at a time. Returns false if rewind is disabled,
+    // no snapshot has been taken yet or a movie is running, the oldest snapshot is loaded again
+    // once the history runs out
+    pub fn rewind_frame(&mut self) -> bool {
+        if self.rewind.is_none() || self.movie.is_some() {
+            return false;
+        }
+        let current = self.save_state();
+        let previous = self.rewind.as_mut().unwrap().previous(&current);
+        match previous {
+            Some(state) => self.load_state(&state).is_ok(),
+            None => false,
+        }
+    }
+
+    // True if the last step finished a frame
+    pub fn frame_ready(&self) -> bool {
+        self.frame_ready
+    }
+
+    // Runs until the PPU has a new frame, returns the M-cycles it took
+    pub fn run_frame(&mut self) -> u32 {
+        let mut cycles = 0;
+        loop {
+            cycles += self.run_on_bus(u32::MAX);
+            if self.frame_ready {
+                return cycles;
+            }
+        }
+    }
+
+    // Runs whole instructions for at least the given number of M-cycles and returns how many
+    // were actually run
+    pub fn run_cycles(&mut self, m_cycles: u32) -> u32 {
+        let mut cycles = 0;
+        while cycles < m_cycles {
+            cycles += self.run_on_bus(m_cycles - cycles);
+        }
+        cycles
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.input.set(button, pressed);
+        if self.movie.is_none() {
+            self.set_joypad(self.input.pressed());
+        }
+    }
+
+    fn set_joypad(&mut self, pressed: u8) {
+        self.joypad.set_pressed(pressed);
+        self.joypad.update_joypad(&mut self.addr_space);
+    }
+
+    // Records the input of every frame from now on, from power-on or from the current state.
+    // Replaces the movie being recorded or played
+    pub fn start_recording(&mut self, from_power_on: bool) {
+        let start_state = if from_power_on {
+            self.reset();
+            None
+        } else {
+            Some(self.save_state())
+        };
+        let movie = Movie::new(
+            self.addr_space.model(),
+            self.addr_space.rom_hash(),
+            self.addr_space.skips_boot(),
+            start_state,
+        );
+        self.movie = Some(MovieSession::Recording(movie));
+        self.next_movie_frame();
+    }
+
+    // Restarts from where the movie starts and plays it, the frontend input is ignored until
+    // it ends
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), MovieError> {
+        movie.check(
+            self.addr_space.model(),
+            self.addr_space.rom_hash(),
+            self.addr_space.skips_boot(),
+        )?;
+        match movie.start_state() {
+            Some(state) => self.load_state(state)?,
+            None => self.reset(),
+        }
+        self.movie = Some(MovieSession::Playing(movie, 0));
+        self.next_movie_frame();
+        Ok(())
+    }
+
+    // Stops the movie being recorded or played and returns it
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        match self.movie.take()? {
+            MovieSession::Recording(movie) | MovieSession::Playing(movie, _) => Some(movie),
+        }
+    }
+
+    pub fn recording_movie(&self) -> bool {
+        matches!(self.movie, Some(MovieSession::Recording(_)))
+    }
+
+    pub fn playing_movie(&self) -> bool {
+        matches!(self.movie, Some(MovieSession::Playing(..)))
+    }
+
+    // Picks the input of the frame that's starting. A finished movie hands the joypad back to
+    // the frontend
+    fn next_movie_frame(&mut self) {
+        let pressed = match &mut self.movie {
+            Some(MovieSession::Recording(movie)) => {
+                movie.push_input(self.input.pressed());
+                self.input.pressed()
+            }
+            Some(MovieSession::Playing(movie, frame)) => {
+                let input = movie.input(*frame);
+                *frame += 1;
+                match input {
+                    Some(pressed) => pressed,
+                    None => {
+                        self.movie = None;
+                        self.input.pressed()
+                    }
+                }
+            }
+            None => return,
+        };
+        self.set_joypad(pressed);
+    }
+
+    // The last frame drawn by the PPU, one ARGB pixel per dot
+    pub fn framebuffer(&self) -> &[u32; WIDTH * HEIGHT] {
+        &self.ppu.pixels
+    }
+
+    // Snapshot of the whole machine, see save_state.rs for the format
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state =
+            StateWriter::with_header(self.addr_space.model(), self.addr_space.rom_hash());
+        self.cpu.save_state(&mut state);
+        self.ppu.save_state(&mut state);
+        self.addr_space.save_state(&mut state);
+        self.joypad.save_state(&mut state);
+        state.write_bool(self.frame_ready);
+        state.into_bytes()
+    }
+
+    // Only accepts states saved with the same model and ROM. The machine is left untouched if
+    // the state can't be loaded
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), SaveStateError> {
+        let current = self.save_state();
+        let result = self.read_state(state);
+        if result.is_err() {
+            self.read_state(&current)
+                .expect("the current state can always be loaded back");
+        }
+        result
+    }
+
+    fn read_state(&mut self, state: &[u8]) -> Result<(), SaveStateError> {
+        let body = save_state::open(state, self.addr_space.model(), self.addr_space.rom_hash())?;
+        let mut state = StateReader::new(&body);
+        self.cpu.load_state(&mut state)?;
+        self.ppu.load_state(&mut state)?;
+        self.addr_space.load_state(&mut state)?;
+        self.joypad.load_state(&mut state)?;
+        self.frame_ready = state.read_bool()?;
+        state.finish()
+    }
+}
@@ -0,0 +1,195 @@
+use super::*;
+
+#[test]
+fn test_run_frame() {
+    // Without a cartridge the CPU runs NOPs, so frames are exactly 154 lines of 456 dots apart
+    let mut gameboy = GameBoy::post_boot(Model::DMG, None);
+    assert_eq!(gameboy.cpu.pc, 0x100);
+    gameboy.run_frame();
+    assert!(gameboy.frame_ready());
+    assert_eq!(gameboy.run_frame(), 154 * 456 / 4);
+    assert_eq!(gameboy.run_cycles(10), 10);
+    assert!(!gameboy.frame_ready());
+
+    gameboy.reset();
+    assert_eq!(gameboy.cpu.pc, 0x100);
+}
+
+#[test]
+fn test_set_button() {
+    let mut gameboy = GameBoy::post_boot(Model::DMG, None);
+    gameboy.addr_space.write(consts::JOYPAD_ADDR, 0x10);
+    gameboy.set_button(Button::Start, true);
+    assert_eq!(gameboy.addr_space.read(consts::JOYPAD_ADDR), 0xD7);
+    gameboy.set_button(Button::Start, false);
+    assert_eq!(gameboy.addr_space.read(consts::JOYPAD_ADDR), 0xDF);
+}
+
+// Keeps writing a counter to work RAM and SCY
+fn counter_rom() -> Vec<u8> {
+    let program = [
+        0x21, 0x00, 0xC0, // LD HL,0xC000
+        0x3C, // INC A
+        0x77, // LD (HL),A
+        0x2C, // INC L
+        0xE0, 0x42, // LDH (SCY),A
+        0x18, 0xF9, // JR -7
+    ];
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+    rom
+}
+
+#[test]
+fn test_save_state_round_trip() {
+    let mut gameboy = GameBoy::post_boot(Model::DMG, Some(counter_rom()));
+    gameboy.set_button(Button::A, true);
+    gameboy.run_frame();
+    gameboy.run_cycles(1000);
+    let state = gameboy.save_state();
+
+    gameboy.run_frame();
+    let expected = gameboy.save_state();
+
+    gameboy.set_button(Button::A, false);
+    gameboy.run_frame();
+    gameboy.load_state(&state).unwrap();
+    assert_eq!(gameboy.save_state(), state);
+    gameboy.run_frame();
+    assert_eq!(gameboy.save_state(), expected);
+}
+
+#[test]
+fn test_load_version_1_state() {
+    let mut gameboy = GameBoy::post_boot(Model::DMG, Some(counter_rom()));
+    gameboy.run_frame();
+    let state = gameboy.save_state();
+
+    // Version 1 had neither the serial port state at the end of the address space, between the
+    // timer and the joypad, nor the serial entry at the end of the scheduler
+    let end = state.len();
+    let serial_entry = end - 2 - 4 - 7 - 17;
+    let entry_count = serial_entry - 4 * 17 - 4;
+    let mut old = state[..serial_entry].to_vec();
+    old.extend_from_slice(&state[end - 2 - 4 - 7..end - 2 - 4]);
+    old.extend_from_slice(&state[end - 2..]);
+    old[entry_count] = 4;
+    old[4] = 1;
+
+    gameboy.run_frame();
+    gameboy.load_state(&old).unwrap();
+    assert_eq!(gameboy.save_state().len(), state.len());
+    assert_eq!(gameboy.addr_space.read(consts::SC_ADDR) & 0x80, 0x00);
+    gameboy.run_frame();
+}
+
+#[test]
+fn test_load_state_errors() {
+    let mut gameboy = GameBoy::post_boot(Model::DMG, Some(counter_rom()));
+    gameboy.run_frame();
+    let state = gameboy.save_state();
+    gameboy.run_frame();
+    let current = gameboy.save_state();
+
+    let other_rom = GameBoy::post_boot(Model::DMG, None).save_state();
+    assert_eq!(
+        gameboy.load_state(&other_rom),
+        Err(SaveStateError::WrongRom)
+    );
+    let other_model = GameBoy::post_boot(Model::MGB, Some(counter_rom())).save_state();
+    assert_eq!(
+        gameboy.load_state(&other_model),
+        Err(SaveStateError::WrongModel {
+            saved: Model::MGB,
+            running: Model::DMG
+        })
+    );
+    let mut newer = state.clone();
+    newer[4] = save_state::VERSION as u8 + 1;
+    assert_eq!(
+        gameboy.load_state(&newer),
+        Err(SaveStateError::UnsupportedVersion(save_state::VERSION + 1))
+    );
+    assert_eq!(
+        gameboy.load_state(&state[..state.len() - 1]),
+        Err(SaveStateError::Truncated)
+    );
+    assert_eq!(
+        gameboy.load_state(b"GB"),
+        Err(SaveStateError::NotASaveState)
+    );
+
+    // Failed loads leave the machine as it was
+    assert_eq!(gameboy.save_state(), current);
+}
+
+#[test]
+fn test_rewind_frame() {
+    let mut gameboy = GameBoy::post_boot(Model::DMG, Some(counter_rom()));
+    assert!(!gameboy.rewind_frame());
+    gameboy.enable_rewind(1, 64 << 20);
+
+    let mut states = Vec::new();
+    for _ in 0..40 {
+        gameboy.run_frame();
+        states.push(gameboy.save_state());
+    }
+    gameboy.run_cycles(100);
+    for state in states.iter().rev() {
+        assert!(gameboy.rewind_frame());
+        assert_eq!(&gameboy.save_state(), state);
+    }
+    // The oldest snapshot stays
+    assert!(gameboy.rewind_frame());
+    assert_eq!(&gameboy.save_state(), &states[0]);
+}
+
+#[test]
+fn test_movie_playback() {
+    // Keeps writing the joypad register to work RAM
+    let program = [
+        0x21, 0x00, 0xC0, // LD HL,0xC000
+        0x3E, 0x10, // LD A,0x10
+        0xE0, 0x00, // LDH (JOYP),A
+        0xF0, 0x00, // LDH A,(JOYP)
+        0x77, // LD (HL),A
+        0x2C, // INC L
+        0x18, 0xFA, // JR -6
+    ];
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x100 + program.len()].copy_from_slice(&program);
+    let buttons = [Button::A, Button::B, Button::Select, Button::Start];
+
+    let mut gameboy = GameBoy::post_boot(Model::DMG, Some(rom.clone()));
+    gameboy.run_frame();
+    for from_power_on in [true, false] {
+        gameboy.start_recording(from_power_on);
+        for frame in 0..20 {
+            gameboy.set_button(buttons[frame % 4], frame % 3 == 0);
+            // Input changes in the middle of a frame only count from the next one
+            gameboy.run_cycles(100);
+            gameboy.set_button(buttons[frame % 2], true);
+            gameboy.run_frame();
+        }
+        let recorded = gameboy.save_state();
+        let movie = gameboy.stop_movie().unwrap();
+        assert_eq!(movie.frames(), 21);
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+
+        let mut player = GameBoy::post_boot(Model::DMG, Some(rom.clone()));
+        if !from_power_on {
+            player.run_frame();
+        }
+        player.play_movie(movie).unwrap();
+        player.set_button(Button::Down, true);
+        for _ in 0..20 {
+            player.run_frame();
+        }
+        assert!(player.playing_movie());
+        assert_eq!(player.save_state(), recorded);
+    }
+
+    let other_rom = GameBoy::post_boot(Model::DMG, None);
+    let movie = Movie::new(Model::DMG, other_rom.addr_space.rom_hash(), true, None);
+    assert_eq!(gameboy.play_movie(movie), Err(MovieError::WrongRom));
+}
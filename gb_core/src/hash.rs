@@ -0,0 +1,6 @@
+// 64-bit FNV-1a. Not cryptographic, only used to tell ROMs and frames apart
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF29CE484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001B3)
+    })
+}
@@ -1,8 +1,58 @@
-use super::addr::*;
+use super::bus::*;
 use super::cpu::*;
+use super::interrupts::*;
+use timing::*;
+#[cfg(test)]
 mod tests;
+pub mod timing;
+
+// Runs the CPU for one instruction, one interrupt dispatch or, while halted or stopped,
+// one M-cycle. Returns the M-cycles taken
+pub fn step(cpu: &mut CPU, bus: &mut impl Bus) -> u32 {
+    // A locked CPU doesn't even service interrupts, but the rest of the system keeps going
+    if cpu.locked {
+        bus.tick();
+        return 1;
+    }
+
+    // Only pressing an input gets the CPU out of STOP
+    if cpu.stopped {
+        if bus.joypad_lines() == 0x0F {
+            bus.tick();
+            return 1;
+        }
+        cpu.stopped = false;
+        bus.stop_timer(false);
+    }
+
+    if cpu.halted {
+        if bus.pending_interrupts() == 0 {
+            bus.tick();
+            return 1;
+        }
+        cpu.halted = false;
+    }
+
+    let cycles = handle_interrupts(cpu, bus);
+    if cycles > 0 {
+        return cycles;
+    }
 
-pub fn exec_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
+    // EI takes effect once the instruction after it is done, unless that instruction is DI
+    let enable_ime = cpu.schedule_ime;
+    let opcode = cpu.next_instr(bus);
+    let cycles = exec_instruction(opcode, cpu, bus);
+    if enable_ime && cpu.schedule_ime {
+        cpu.schedule_ime = false;
+        cpu.ime = true;
+    }
+    cycles as u32
+}
+
+// Executes an already fetched opcode and returns the M-cycles the instruction took, fetch included
+pub fn exec_instruction(opcode: u8, cpu: &mut CPU, bus: &mut impl Bus) -> u8 {
+    // Branches don't change the flags, so whether one is taken can be known up front
+    let taken = condition(opcode, cpu);
     match opcode {
         0x7F => ld_r_r(Register::A, Register::A, cpu),
         0x78 => ld_r_r(Register::A, Register::B, cpu),
@@ -60,63 +110,63 @@ pub fn exec_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace) -
         0x6C => ld_r_r(Register::L, Register::H, cpu),
         0x6D => ld_r_r(Register::L, Register::L, cpu),
 
-        0x3E => ld_r_n(Register::A, cpu, addr_space),
-        0x06 => ld_r_n(Register::B, cpu, addr_space),
-        0x0E => ld_r_n(Register::C, cpu, addr_space),
-        0x16 => ld_r_n(Register::D, cpu, addr_space),
-        0x1E => ld_r_n(Register::E, cpu, addr_space),
-        0x26 => ld_r_n(Register::H, cpu, addr_space),
-        0x2E => ld_r_n(Register::L, cpu, addr_space),
-
-        0x77 => ld_hl_r(Register::A, cpu, addr_space),
-        0x70 => ld_hl_r(Register::B, cpu, addr_space),
-        0x71 => ld_hl_r(Register::C, cpu, addr_space),
-        0x72 => ld_hl_r(Register::D, cpu, addr_space),
-        0x73 => ld_hl_r(Register::E, cpu, addr_space),
-        0x74 => ld_hl_r(Register::H, cpu, addr_space),
-        0x75 => ld_hl_r(Register::L, cpu, addr_space),
-
-        0x7E => ld_r_hl(Register::A, cpu, addr_space),
-        0x46 => ld_r_hl(Register::B, cpu, addr_space),
-        0x4E => ld_r_hl(Register::C, cpu, addr_space),
-        0x56 => ld_r_hl(Register::D, cpu, addr_space),
-        0x5E => ld_r_hl(Register::E, cpu, addr_space),
-        0x66 => ld_r_hl(Register::H, cpu, addr_space),
-        0x6E => ld_r_hl(Register::L, cpu, addr_space),
-
-        0x01 => ld_rr_nn(Register16::BC, cpu, addr_space),
-        0x11 => ld_rr_nn(Register16::DE, cpu, addr_space),
-        0x21 => ld_rr_nn(Register16::HL, cpu, addr_space),
-        0x31 => ld_rr_nn(Register16::SP, cpu, addr_space),
-
-        0xF5 => push_rr(Register::A, Register::F, cpu, addr_space),
-        0xC5 => push_rr(Register::B, Register::C, cpu, addr_space),
-        0xD5 => push_rr(Register::D, Register::E, cpu, addr_space),
-        0xE5 => push_rr(Register::H, Register::L, cpu, addr_space),
-
-        0xF1 => pop_rr(Register::A, Register::F, cpu, addr_space),
-        0xC1 => pop_rr(Register::B, Register::C, cpu, addr_space),
-        0xD1 => pop_rr(Register::D, Register::E, cpu, addr_space),
-        0xE1 => pop_rr(Register::H, Register::L, cpu, addr_space),
-
-        0x36 => ld_hl_n(cpu, addr_space),
-        0x0A => ld_a_bc(cpu, addr_space),
-        0x1A => ld_a_de(cpu, addr_space),
-        0x02 => ld_bc_a(cpu, addr_space),
-        0x12 => ld_de_a(cpu, addr_space),
-        0xFA => ld_a_nn(cpu, addr_space),
-        0xEA => ld_nn_a(cpu, addr_space),
-        0xF2 => ldh_a_c(cpu, addr_space),
-        0xE2 => ldh_c_a(cpu, addr_space),
-        0xF0 => ldh_a_n(cpu, addr_space),
-        0xE0 => ldh_n_a(cpu, addr_space),
-        0x3A => ld_a_hl_dec(cpu, addr_space),
-        0x32 => ld_hl_dec_a(cpu, addr_space),
-        0x2A => ld_a_hl_inc(cpu, addr_space),
-        0x22 => ld_hl_inc_a(cpu, addr_space),
-        0x08 => ld_nn_sp(cpu, addr_space),
-        0xF8 => ldhl_sp_n(cpu, addr_space),
-        0xF9 => ld_sp_hl(cpu),
+        0x3E => ld_r_n(Register::A, cpu, bus),
+        0x06 => ld_r_n(Register::B, cpu, bus),
+        0x0E => ld_r_n(Register::C, cpu, bus),
+        0x16 => ld_r_n(Register::D, cpu, bus),
+        0x1E => ld_r_n(Register::E, cpu, bus),
+        0x26 => ld_r_n(Register::H, cpu, bus),
+        0x2E => ld_r_n(Register::L, cpu, bus),
+
+        0x77 => ld_hl_r(Register::A, cpu, bus),
+        0x70 => ld_hl_r(Register::B, cpu, bus),
+        0x71 => ld_hl_r(Register::C, cpu, bus),
+        0x72 => ld_hl_r(Register::D, cpu, bus),
+        0x73 => ld_hl_r(Register::E, cpu, bus),
+        0x74 => ld_hl_r(Register::H, cpu, bus),
+        0x75 => ld_hl_r(Register::L, cpu, bus),
+
+        0x7E => ld_r_hl(Register::A, cpu, bus),
+        0x46 => ld_r_hl(Register::B, cpu, bus),
+        0x4E => ld_r_hl(Register::C, cpu, bus),
+        0x56 => ld_r_hl(Register::D, cpu, bus),
+        0x5E => ld_r_hl(Register::E, cpu, bus),
+        0x66 => ld_r_hl(Register::H, cpu, bus),
+        0x6E => ld_r_hl(Register::L, cpu, bus),
+
+        0x01 => ld_rr_nn(Register16::BC, cpu, bus),
+        0x11 => ld_rr_nn(Register16::DE, cpu, bus),
+        0x21 => ld_rr_nn(Register16::HL, cpu, bus),
+        0x31 => ld_rr_nn(Register16::SP, cpu, bus),
+
+        0xF5 => push_rr(Register::A, Register::F, cpu, bus),
+        0xC5 => push_rr(Register::B, Register::C, cpu, bus),
+        0xD5 => push_rr(Register::D, Register::E, cpu, bus),
+        0xE5 => push_rr(Register::H, Register::L, cpu, bus),
+
+        0xF1 => pop_rr(Register::A, Register::F, cpu, bus),
+        0xC1 => pop_rr(Register::B, Register::C, cpu, bus),
+        0xD1 => pop_rr(Register::D, Register::E, cpu, bus),
+        0xE1 => pop_rr(Register::H, Register::L, cpu, bus),
+
+        0x36 => ld_hl_n(cpu, bus),
+        0x0A => ld_a_bc(cpu, bus),
+        0x1A => ld_a_de(cpu, bus),
+        0x02 => ld_bc_a(cpu, bus),
+        0x12 => ld_de_a(cpu, bus),
+        0xFA => ld_a_nn(cpu, bus),
+        0xEA => ld_nn_a(cpu, bus),
+        0xF2 => ldh_a_c(cpu, bus),
+        0xE2 => ldh_c_a(cpu, bus),
+        0xF0 => ldh_a_n(cpu, bus),
+        0xE0 => ldh_n_a(cpu, bus),
+        0x3A => ld_a_hl_dec(cpu, bus),
+        0x32 => ld_hl_dec_a(cpu, bus),
+        0x2A => ld_a_hl_inc(cpu, bus),
+        0x22 => ld_hl_inc_a(cpu, bus),
+        0x08 => ld_nn_sp(cpu, bus),
+        0xF8 => ldhl_sp_n(cpu, bus),
+        0xF9 => ld_sp_hl(cpu, bus),
 
         0x87 => add_a_n(cpu.a, cpu),
         0x80 => add_a_n(cpu.b, cpu),
@@ -125,8 +175,8 @@ pub fn exec_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace) -
         0x83 => add_a_n(cpu.e, cpu),
         0x84 => add_a_n(cpu.h, cpu),
         0x85 => add_a_n(cpu.l, cpu),
-        0x86 => add_a_hl(cpu, addr_space),
-        0xC6 => add_a_imm(cpu, addr_space),
+        0x86 => add_a_hl(cpu, bus),
+        0xC6 => add_a_imm(cpu, bus),
 
         0x8F => adc_a_n(cpu.a, cpu),
         0x88 => adc_a_n(cpu.b, cpu),
@@ -135,8 +185,8 @@ pub fn exec_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace) -
         0x8B => adc_a_n(cpu.e, cpu),
         0x8C => adc_a_n(cpu.h, cpu),
         0x8D => adc_a_n(cpu.l, cpu),
-        0x8E => adc_a_hl(cpu, addr_space),
-        0xCE => adc_a_imm(cpu, addr_space),
+        0x8E => adc_a_hl(cpu, bus),
+        0xCE => adc_a_imm(cpu, bus),
 
         0x97 => sub_a_n(cpu.a, cpu),
         0x90 => sub_a_n(cpu.b, cpu),
@@ -145,8 +195,8 @@ pub fn exec_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace) -
         0x93 => sub_a_n(cpu.e, cpu),
         0x94 => sub_a_n(cpu.h, cpu),
         0x95 => sub_a_n(cpu.l, cpu),
-        0x96 => sub_a_hl(cpu, addr_space),
-        0xD6 => sub_a_imm(cpu, addr_space),
+        0x96 => sub_a_hl(cpu, bus),
+        0xD6 => sub_a_imm(cpu, bus),
 
         0x9F => sbc_a_n(cpu.a, cpu),
         0x98 => sbc_a_n(cpu.b, cpu),
@@ -155,8 +205,8 @@ pub fn exec_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace) -
         0x9B => sbc_a_n(cpu.e, cpu),
         0x9C => sbc_a_n(cpu.h, cpu),
         0x9D => sbc_a_n(cpu.l, cpu),
-        0x9E => sbc_a_hl(cpu, addr_space),
-        0xDE => sbc_a_imm(cpu, addr_space),
+        0x9E => sbc_a_hl(cpu, bus),
+        0xDE => sbc_a_imm(cpu, bus),
 
         0xA7 => and_a_n(cpu.a, cpu),
         0xA0 => and_a_n(cpu.b, cpu),
@@ -165,8 +215,8 @@ pub fn exec_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace) -
         0xA3 => and_a_n(cpu.e, cpu),
         0xA4 => and_a_n(cpu.h, cpu),
         0xA5 => and_a_n(cpu.l, cpu),
-        0xA6 => and_a_hl(cpu, addr_space),
-        0xE6 => and_a_imm(cpu, addr_space),
+        0xA6 => and_a_hl(cpu, bus),
+        0xE6 => and_a_imm(cpu, bus),
 
         0xB7 => or_a_n(cpu.a, cpu),
         0xB0 => or_a_n(cpu.b, cpu),
@@ -175,8 +225,8 @@ pub fn exec_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace) -
         0xB3 => or_a_n(cpu.e, cpu),
         0xB4 => or_a_n(cpu.h, cpu),
         0xB5 => or_a_n(cpu.l, cpu),
-        0xB6 => or_a_hl(cpu, addr_space),
-        0xF6 => or_a_imm(cpu, addr_space),
+        0xB6 => or_a_hl(cpu, bus),
+        0xF6 => or_a_imm(cpu, bus),
 
         0xAF => xor_a_n(cpu.a, cpu),
         0xA8 => xor_a_n(cpu.b, cpu),
@@ -185,8 +235,8 @@ pub fn exec_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace) -
         0xAB => xor_a_n(cpu.e, cpu),
         0xAC => xor_a_n(cpu.h, cpu),
         0xAD => xor_a_n(cpu.l, cpu),
-        0xAE => xor_a_hl(cpu, addr_space),
-        0xEE => xor_a_imm(cpu, addr_space),
+        0xAE => xor_a_hl(cpu, bus),
+        0xEE => xor_a_imm(cpu, bus),
 
         0xBF => cp_a_n(cpu.a, cpu),
         0xB8 => cp_a_n(cpu.b, cpu),
@@ -195,8 +245,8 @@ pub fn exec_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace) -
         0xBB => cp_a_n(cpu.e, cpu),
         0xBC => cp_a_n(cpu.h, cpu),
         0xBD => cp_a_n(cpu.l, cpu),
-        0xBE => cp_a_hl(cpu, addr_space),
-        0xFE => cp_a_imm(cpu, addr_space),
+        0xBE => cp_a_hl(cpu, bus),
+        0xFE => cp_a_imm(cpu, bus),
 
         0x3C => inc_r(Register::A, cpu),
         0x04 => inc_r(Register::B, cpu),
@@ -205,7 +255,7 @@ pub fn exec_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace) -
         0x1C => inc_r(Register::E, cpu),
         0x24 => inc_r(Register::H, cpu),
         0x2C => inc_r(Register::L, cpu),
-        0x34 => inc_hl(cpu, addr_space),
+        0x34 => inc_hl(cpu, bus),
 
         0x3D => dec_r(Register::A, cpu),
         0x05 => dec_r(Register::B, cpu),
@@ -214,64 +264,64 @@ pub fn exec_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace) -
         0x1D => dec_r(Register::E, cpu),
         0x25 => dec_r(Register::H, cpu),
         0x2D => dec_r(Register::L, cpu),
-        0x35 => dec_hl(cpu, addr_space),
+        0x35 => dec_hl(cpu, bus),
 
-        0x09 => add_hl_nn(cpu.bc(), cpu),
-        0x19 => add_hl_nn(cpu.de(), cpu),
-        0x29 => add_hl_nn(cpu.hl(), cpu),
-        0x39 => add_hl_nn(cpu.sp, cpu),
+        0x09 => add_hl_nn(cpu.bc(), cpu, bus),
+        0x19 => add_hl_nn(cpu.de(), cpu, bus),
+        0x29 => add_hl_nn(cpu.hl(), cpu, bus),
+        0x39 => add_hl_nn(cpu.sp, cpu, bus),
 
-        0xE8 => add_sp_imm(cpu, addr_space),
+        0xE8 => add_sp_imm(cpu, bus),
 
-        0x03 => inc_rr(Register16::BC, cpu),
-        0x13 => inc_rr(Register16::DE, cpu),
-        0x23 => inc_rr(Register16::HL, cpu),
-        0x33 => inc_rr(Register16::SP, cpu),
+        0x03 => inc_rr(Register16::BC, cpu, bus),
+        0x13 => inc_rr(Register16::DE, cpu, bus),
+        0x23 => inc_rr(Register16::HL, cpu, bus),
+        0x33 => inc_rr(Register16::SP, cpu, bus),
 
-        0x0B => dec_rr(Register16::BC, cpu),
-        0x1B => dec_rr(Register16::DE, cpu),
-        0x2B => dec_rr(Register16::HL, cpu),
-        0x3B => dec_rr(Register16::SP, cpu),
+        0x0B => dec_rr(Register16::BC, cpu, bus),
+        0x1B => dec_rr(Register16::DE, cpu, bus),
+        0x2B => dec_rr(Register16::HL, cpu, bus),
+        0x3B => dec_rr(Register16::SP, cpu, bus),
 
-        0xC3 => jp_nn(cpu, addr_space),
-        0x18 => jr_e(cpu, addr_space),
+        0xC3 => jp_nn(cpu, bus),
+        0x18 => jr_e(cpu, bus),
         0xE9 => jp_hl(cpu),
-        0x20 => jr_cc_e(!cpu.zero_flag(), cpu, addr_space),
-        0x28 => jr_cc_e(cpu.zero_flag(), cpu, addr_space),
-        0x30 => jr_cc_e(!cpu.carry_flag(), cpu, addr_space),
-        0x38 => jr_cc_e(cpu.carry_flag(), cpu, addr_space),
-
-        0xC2 => jp_cc_nn(!cpu.zero_flag(), cpu, addr_space),
-        0xCA => jp_cc_nn(cpu.zero_flag(), cpu, addr_space),
-        0xD2 => jp_cc_nn(!cpu.carry_flag(), cpu, addr_space),
-        0xDA => jp_cc_nn(cpu.carry_flag(), cpu, addr_space),
-
-        0xCD => call_cc_nn(true, cpu, addr_space),
-        0xC4 => call_cc_nn(!cpu.zero_flag(), cpu, addr_space),
-        0xCC => call_cc_nn(cpu.zero_flag(), cpu, addr_space),
-        0xD4 => call_cc_nn(!cpu.carry_flag(), cpu, addr_space),
-        0xDC => call_cc_nn(cpu.carry_flag(), cpu, addr_space),
-
-        0xC9 => ret_cc(true, cpu, addr_space),
-        0xC0 => ret_cc(!cpu.zero_flag(), cpu, addr_space),
-        0xC8 => ret_cc(cpu.zero_flag(), cpu, addr_space),
-        0xD0 => ret_cc(!cpu.carry_flag(), cpu, addr_space),
-        0xD8 => ret_cc(cpu.carry_flag(), cpu, addr_space),
-
-        0xD9 => reti(cpu, addr_space),
+        0x20 => jr_cc_e(!cpu.zero_flag(), cpu, bus),
+        0x28 => jr_cc_e(cpu.zero_flag(), cpu, bus),
+        0x30 => jr_cc_e(!cpu.carry_flag(), cpu, bus),
+        0x38 => jr_cc_e(cpu.carry_flag(), cpu, bus),
+
+        0xC2 => jp_cc_nn(!cpu.zero_flag(), cpu, bus),
+        0xCA => jp_cc_nn(cpu.zero_flag(), cpu, bus),
+        0xD2 => jp_cc_nn(!cpu.carry_flag(), cpu, bus),
+        0xDA => jp_cc_nn(cpu.carry_flag(), cpu, bus),
+
+        0xCD => call_cc_nn(true, cpu, bus),
+        0xC4 => call_cc_nn(!cpu.zero_flag(), cpu, bus),
+        0xCC => call_cc_nn(cpu.zero_flag(), cpu, bus),
+        0xD4 => call_cc_nn(!cpu.carry_flag(), cpu, bus),
+        0xDC => call_cc_nn(cpu.carry_flag(), cpu, bus),
+
+        0xC9 => ret(cpu, bus),
+        0xC0 => ret_cc(!cpu.zero_flag(), cpu, bus),
+        0xC8 => ret_cc(cpu.zero_flag(), cpu, bus),
+        0xD0 => ret_cc(!cpu.carry_flag(), cpu, bus),
+        0xD8 => ret_cc(cpu.carry_flag(), cpu, bus),
+
+        0xD9 => reti(cpu, bus),
 
         0xFB => ei(cpu),
         0xF3 => di(cpu),
 
         0x2F => cpl(cpu),
-        0xC7 => rst_n(0x00, cpu, addr_space),
-        0xCF => rst_n(0x08, cpu, addr_space),
-        0xD7 => rst_n(0x10, cpu, addr_space),
-        0xDF => rst_n(0x18, cpu, addr_space),
-        0xE7 => rst_n(0x20, cpu, addr_space),
-        0xEF => rst_n(0x28, cpu, addr_space),
-        0xF7 => rst_n(0x30, cpu, addr_space),
-        0xFF => rst_n(0x38, cpu, addr_space),
+        0xC7 => rst_n(0x00, cpu, bus),
+        0xCF => rst_n(0x08, cpu, bus),
+        0xD7 => rst_n(0x10, cpu, bus),
+        0xDF => rst_n(0x18, cpu, bus),
+        0xE7 => rst_n(0x20, cpu, bus),
+        0xEF => rst_n(0x28, cpu, bus),
+        0xF7 => rst_n(0x30, cpu, bus),
+        0xFF => rst_n(0x38, cpu, bus),
 
         0x37 => scf(cpu),
         0x3F => ccf(cpu),
@@ -283,17 +333,36 @@ pub fn exec_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace) -
 
         0x27 => daa(cpu),
 
-        0x00 => 1, //NOP
-        0x10 => stop(),
-        0x76 => halt(),
+        0x00 => {} //NOP
+        0x10 => stop(cpu, bus),
+        0x76 => halt(cpu, bus),
+
+        0xD3 | 0xE3 | 0xE4 | 0xF4 | 0xDB | 0xDD | 0xEB | 0xEC | 0xED | 0xFC | 0xFD => {
+            lock(opcode, cpu)
+        }
 
-        0xD3 | 0xE3 | 0xE4 | 0xF4 | 0xDB | 0xDD | 0xEB | 0xEC | 0xED | 0xFC | 0xFD => 1, //Invalid instr
+        0xCB => {
+            let cb_opcode = cpu.next_instr(bus);
+            return exec_cb_instruction(cb_opcode, cpu, bus);
+        }
+    }
+    match BRANCH_CYCLES[opcode as usize] {
+        cycles if cycles > 0 && taken => cycles,
+        _ => CYCLES[opcode as usize],
+    }
+}
 
-        0xCB => exec_cb_instruction(cpu.next_instr(addr_space), cpu, addr_space),
+// Condition encoded in bits 3-4 of the conditional JR/JP/CALL/RET opcodes
+fn condition(opcode: u8, cpu: &CPU) -> bool {
+    match (opcode >> 3) & 0x3 {
+        0 => !cpu.zero_flag(),
+        1 => cpu.zero_flag(),
+        2 => !cpu.carry_flag(),
+        _ => cpu.carry_flag(),
     }
 }
 
-pub fn exec_cb_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
+pub fn exec_cb_instruction(opcode: u8, cpu: &mut CPU, bus: &mut impl Bus) -> u8 {
     match opcode {
         0x07 => rlc_r(Register::A, cpu),
         0x00 => rlc_r(Register::B, cpu),
@@ -302,7 +371,7 @@ pub fn exec_cb_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace
         0x03 => rlc_r(Register::E, cpu),
         0x04 => rlc_r(Register::H, cpu),
         0x05 => rlc_r(Register::L, cpu),
-        0x06 => rlc_hl(cpu, addr_space),
+        0x06 => rlc_hl(cpu, bus),
 
         0x17 => rl_r(Register::A, cpu),
         0x10 => rl_r(Register::B, cpu),
@@ -311,7 +380,7 @@ pub fn exec_cb_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace
         0x13 => rl_r(Register::E, cpu),
         0x14 => rl_r(Register::H, cpu),
         0x15 => rl_r(Register::L, cpu),
-        0x16 => rl_hl(cpu, addr_space),
+        0x16 => rl_hl(cpu, bus),
 
         0x37 => swap_r(Register::A, cpu),
         0x30 => swap_r(Register::B, cpu),
@@ -320,7 +389,7 @@ pub fn exec_cb_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace
         0x33 => swap_r(Register::E, cpu),
         0x34 => swap_r(Register::H, cpu),
         0x35 => swap_r(Register::L, cpu),
-        0x36 => swap_hl(cpu, addr_space),
+        0x36 => swap_hl(cpu, bus),
 
         0x87 => res_b_r(0, Register::A, cpu),
         0x80 => res_b_r(0, Register::B, cpu),
@@ -386,14 +455,14 @@ pub fn exec_cb_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace
         0xBC => res_b_r(7, Register::H, cpu),
         0xBD => res_b_r(7, Register::L, cpu),
 
-        0x86 => res_b_hl(0, cpu, addr_space),
-        0x8E => res_b_hl(1, cpu, addr_space),
-        0x96 => res_b_hl(2, cpu, addr_space),
-        0x9E => res_b_hl(3, cpu, addr_space),
-        0xA6 => res_b_hl(4, cpu, addr_space),
-        0xAE => res_b_hl(5, cpu, addr_space),
-        0xB6 => res_b_hl(6, cpu, addr_space),
-        0xBE => res_b_hl(7, cpu, addr_space),
+        0x86 => res_b_hl(0, cpu, bus),
+        0x8E => res_b_hl(1, cpu, bus),
+        0x96 => res_b_hl(2, cpu, bus),
+        0x9E => res_b_hl(3, cpu, bus),
+        0xA6 => res_b_hl(4, cpu, bus),
+        0xAE => res_b_hl(5, cpu, bus),
+        0xB6 => res_b_hl(6, cpu, bus),
+        0xBE => res_b_hl(7, cpu, bus),
 
         0xC7 => set_b_r(0, Register::A, cpu),
         0xC0 => set_b_r(0, Register::B, cpu),
@@ -459,14 +528,14 @@ pub fn exec_cb_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace
         0xFC => set_b_r(7, Register::H, cpu),
         0xFD => set_b_r(7, Register::L, cpu),
 
-        0xC6 => set_n_hl(0, cpu, addr_space),
-        0xCE => set_n_hl(1, cpu, addr_space),
-        0xD6 => set_n_hl(2, cpu, addr_space),
-        0xDE => set_n_hl(3, cpu, addr_space),
-        0xE6 => set_n_hl(4, cpu, addr_space),
-        0xEE => set_n_hl(5, cpu, addr_space),
-        0xF6 => set_n_hl(6, cpu, addr_space),
-        0xFE => set_n_hl(7, cpu, addr_space),
+        0xC6 => set_n_hl(0, cpu, bus),
+        0xCE => set_n_hl(1, cpu, bus),
+        0xD6 => set_n_hl(2, cpu, bus),
+        0xDE => set_n_hl(3, cpu, bus),
+        0xE6 => set_n_hl(4, cpu, bus),
+        0xEE => set_n_hl(5, cpu, bus),
+        0xF6 => set_n_hl(6, cpu, bus),
+        0xFE => set_n_hl(7, cpu, bus),
 
         0x3F => srl_r(Register::A, cpu),
         0x38 => srl_r(Register::B, cpu),
@@ -475,7 +544,7 @@ pub fn exec_cb_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace
         0x3B => srl_r(Register::E, cpu),
         0x3C => srl_r(Register::H, cpu),
         0x3D => srl_r(Register::L, cpu),
-        0x3E => srl_hl(cpu, addr_space),
+        0x3E => srl_hl(cpu, bus),
 
         0x0F => rrc_r(Register::A, cpu),
         0x08 => rrc_r(Register::B, cpu),
@@ -484,7 +553,7 @@ pub fn exec_cb_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace
         0x0B => rrc_r(Register::E, cpu),
         0x0C => rrc_r(Register::H, cpu),
         0x0D => rrc_r(Register::L, cpu),
-        0x0E => rrc_hl(cpu, addr_space),
+        0x0E => rrc_hl(cpu, bus),
 
         0x1F => rr_r(Register::A, cpu),
         0x18 => rr_r(Register::B, cpu),
@@ -493,7 +562,7 @@ pub fn exec_cb_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace
         0x1B => rr_r(Register::E, cpu),
         0x1C => rr_r(Register::H, cpu),
         0x1D => rr_r(Register::L, cpu),
-        0x1E => rr_hl(cpu, addr_space),
+        0x1E => rr_hl(cpu, bus),
 
         0x27 => sla_r(Register::A, cpu),
         0x20 => sla_r(Register::B, cpu),
@@ -502,7 +571,7 @@ pub fn exec_cb_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace
         0x23 => sla_r(Register::E, cpu),
         0x24 => sla_r(Register::H, cpu),
         0x25 => sla_r(Register::L, cpu),
-        0x26 => sla_hl(cpu, addr_space),
+        0x26 => sla_hl(cpu, bus),
 
         0x2F => sra_r(Register::A, cpu),
         0x28 => sra_r(Register::B, cpu),
@@ -511,7 +580,7 @@ pub fn exec_cb_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace
         0x2B => sra_r(Register::E, cpu),
         0x2C => sra_r(Register::H, cpu),
         0x2D => sra_r(Register::L, cpu),
-        0x2E => sra_hl(cpu, addr_space),
+        0x2E => sra_hl(cpu, bus),
 
         0x40 => bit_n_r(0, Register::B, cpu),
         0x50 => bit_n_r(2, Register::B, cpu),
@@ -583,187 +652,164 @@ pub fn exec_cb_instruction(opcode: u8, cpu: &mut CPU, addr_space: &mut AddrSpace
         0x6F => bit_n_r(5, Register::A, cpu),
         0x7F => bit_n_r(7, Register::A, cpu),
 
-        0x46 => bit_n_hl(0, cpu, addr_space),
-        0x56 => bit_n_hl(2, cpu, addr_space),
-        0x66 => bit_n_hl(4, cpu, addr_space),
-        0x76 => bit_n_hl(6, cpu, addr_space),
+        0x46 => bit_n_hl(0, cpu, bus),
+        0x56 => bit_n_hl(2, cpu, bus),
+        0x66 => bit_n_hl(4, cpu, bus),
+        0x76 => bit_n_hl(6, cpu, bus),
 
-        0x4E => bit_n_hl(1, cpu, addr_space),
-        0x5E => bit_n_hl(3, cpu, addr_space),
-        0x6E => bit_n_hl(5, cpu, addr_space),
-        0x7E => bit_n_hl(7, cpu, addr_space),
+        0x4E => bit_n_hl(1, cpu, bus),
+        0x5E => bit_n_hl(3, cpu, bus),
+        0x6E => bit_n_hl(5, cpu, bus),
+        0x7E => bit_n_hl(7, cpu, bus),
     }
+    CB_CYCLES[opcode as usize]
 }
 
 // 8-bit load instructions
-fn ld_r_r(register1: Register, register2: Register, cpu: &mut CPU) -> u8 {
+fn ld_r_r(register1: Register, register2: Register, cpu: &mut CPU) {
     cpu.set_reg(register1, cpu.get_reg(register2));
-    1
 }
 
 // Load immediate value into register
-fn ld_r_n(register: Register, cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    let n = cpu.next_instr(addr_space);
+fn ld_r_n(register: Register, cpu: &mut CPU, bus: &mut impl Bus) {
+    let n = cpu.next_instr(bus);
     cpu.set_reg(register, n);
-    2
 }
 
 // Load from the address in HL into register
-fn ld_r_hl(register: Register, cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
-    let hl_value = addr_space.read(cpu.hl());
+fn ld_r_hl(register: Register, cpu: &mut CPU, bus: &mut impl Bus) {
+    let hl_value = bus.read(cpu.hl());
     cpu.set_reg(register, hl_value);
-    2
 }
 
 // Load to the address in HL the data in the register
-fn ld_hl_r(register: Register, cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
-    addr_space.write(cpu.hl(), cpu.get_reg(register));
-    2
+fn ld_hl_r(register: Register, cpu: &mut CPU, bus: &mut impl Bus) {
+    bus.write(cpu.hl(), cpu.get_reg(register));
 }
 
-fn ld_hl_n(cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
-    addr_space.write(cpu.hl(), cpu.next_instr(addr_space));
-    3
+fn ld_hl_n(cpu: &mut CPU, bus: &mut impl Bus) {
+    let n = cpu.next_instr(bus);
+    bus.write(cpu.hl(), n);
 }
 
-fn ld_a_bc(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    cpu.a = addr_space.read(cpu.bc());
-    2
+fn ld_a_bc(cpu: &mut CPU, bus: &mut impl Bus) {
+    cpu.a = bus.read(cpu.bc());
 }
 
-fn ld_a_de(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    cpu.a = addr_space.read(cpu.de());
-    2
+fn ld_a_de(cpu: &mut CPU, bus: &mut impl Bus) {
+    cpu.a = bus.read(cpu.de());
 }
 
-fn ld_bc_a(cpu: &CPU, addr_space: &mut AddrSpace) -> u8 {
-    addr_space.write(cpu.bc(), cpu.a);
-    2
+fn ld_bc_a(cpu: &CPU, bus: &mut impl Bus) {
+    bus.write(cpu.bc(), cpu.a);
 }
 
-fn ld_de_a(cpu: &CPU, addr_space: &mut AddrSpace) -> u8 {
-    addr_space.write(cpu.de(), cpu.a);
-    2
+fn ld_de_a(cpu: &CPU, bus: &mut impl Bus) {
+    bus.write(cpu.de(), cpu.a);
 }
 
-fn ld_a_nn(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    let nn = (cpu.next_instr(addr_space) as u16) | ((cpu.next_instr(addr_space) as u16) << 8);
-    cpu.a = addr_space.read(nn);
-    4
+fn ld_a_nn(cpu: &mut CPU, bus: &mut impl Bus) {
+    let nn = (cpu.next_instr(bus) as u16) | ((cpu.next_instr(bus) as u16) << 8);
+    cpu.a = bus.read(nn);
 }
 
-fn ld_nn_a(cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
-    let nn = (cpu.next_instr(addr_space) as u16) | ((cpu.next_instr(addr_space) as u16) << 8);
-    addr_space.write(nn, cpu.a);
-    4
+fn ld_nn_a(cpu: &mut CPU, bus: &mut impl Bus) {
+    let nn = (cpu.next_instr(bus) as u16) | ((cpu.next_instr(bus) as u16) << 8);
+    bus.write(nn, cpu.a);
 }
 
-fn ldh_a_c(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
+fn ldh_a_c(cpu: &mut CPU, bus: &mut impl Bus) {
     let addr = (cpu.c as u16) | 0xFF00;
-    cpu.a = addr_space.read(addr);
-    2
+    cpu.a = bus.read(addr);
 }
 
-fn ldh_c_a(cpu: &CPU, addr_space: &mut AddrSpace) -> u8 {
+fn ldh_c_a(cpu: &CPU, bus: &mut impl Bus) {
     let addr = (cpu.c as u16) | 0xFF00;
-    addr_space.write(addr, cpu.a);
-    2
+    bus.write(addr, cpu.a);
 }
 
-fn ldh_a_n(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    let addr = (cpu.next_instr(addr_space) as u16) | 0xFF00;
-    cpu.a = addr_space.read(addr);
-    3
+fn ldh_a_n(cpu: &mut CPU, bus: &mut impl Bus) {
+    let addr = (cpu.next_instr(bus) as u16) | 0xFF00;
+    cpu.a = bus.read(addr);
 }
 
-fn ldh_n_a(cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
-    let addr = (cpu.next_instr(addr_space) as u16) | 0xFF00;
-    addr_space.write(addr, cpu.a);
-    3
+fn ldh_n_a(cpu: &mut CPU, bus: &mut impl Bus) {
+    let addr = (cpu.next_instr(bus) as u16) | 0xFF00;
+    bus.write(addr, cpu.a);
 }
 
-fn ld_a_hl_dec(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    cpu.a = addr_space.read(cpu.hl());
+fn ld_a_hl_dec(cpu: &mut CPU, bus: &mut impl Bus) {
+    cpu.a = bus.read_inc_dec(cpu.hl());
     cpu.set_reg16(Register16::HL, cpu.hl().wrapping_sub(1));
-    2
 }
 
-fn ld_hl_dec_a(cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
-    addr_space.write(cpu.hl(), cpu.a);
+fn ld_hl_dec_a(cpu: &mut CPU, bus: &mut impl Bus) {
+    bus.write(cpu.hl(), cpu.a);
     cpu.set_reg16(Register16::HL, cpu.hl().wrapping_sub(1));
-    2
 }
 
-fn ld_a_hl_inc(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    cpu.a = addr_space.read(cpu.hl());
+fn ld_a_hl_inc(cpu: &mut CPU, bus: &mut impl Bus) {
+    cpu.a = bus.read_inc_dec(cpu.hl());
     cpu.set_reg16(Register16::HL, cpu.hl().wrapping_add(1));
-    2
 }
 
-fn ld_hl_inc_a(cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
-    addr_space.write(cpu.hl(), cpu.a);
+fn ld_hl_inc_a(cpu: &mut CPU, bus: &mut impl Bus) {
+    bus.write(cpu.hl(), cpu.a);
     cpu.set_reg16(Register16::HL, cpu.hl().wrapping_add(1));
-    2
 }
 
 // 16-bit load instructions
 
-fn ld_rr_nn(register: Register16, cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    let nn = (cpu.next_instr(addr_space) as u16) | ((cpu.next_instr(addr_space) as u16) << 8);
+fn ld_rr_nn(register: Register16, cpu: &mut CPU, bus: &mut impl Bus) {
+    let nn = (cpu.next_instr(bus) as u16) | ((cpu.next_instr(bus) as u16) << 8);
     cpu.set_reg16(register, nn);
-    3
 }
 
-fn ld_nn_sp(cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
-    let nn = (cpu.next_instr(addr_space) as u16) | ((cpu.next_instr(addr_space) as u16) << 8);
-    addr_space.write(nn, (cpu.sp & 0x00FF) as u8);
-    addr_space.write(nn + 1, ((cpu.sp & 0xFF00) >> 8) as u8);
-    5
+fn ld_nn_sp(cpu: &mut CPU, bus: &mut impl Bus) {
+    let nn = (cpu.next_instr(bus) as u16) | ((cpu.next_instr(bus) as u16) << 8);
+    bus.write(nn, (cpu.sp & 0x00FF) as u8);
+    bus.write(nn + 1, ((cpu.sp & 0xFF00) >> 8) as u8);
 }
 
-fn ld_sp_hl(cpu: &mut CPU) -> u8 {
+fn ld_sp_hl(cpu: &mut CPU, bus: &mut impl Bus) {
     cpu.sp = cpu.hl();
-    2
+    bus.tick_inc_dec(cpu.sp);
 }
 
-fn push_rr(reg1: Register, reg2: Register, cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
-    addr_space.write(cpu.sp - 1, cpu.get_reg(reg1));
-    addr_space.write(cpu.sp - 2, cpu.get_reg(reg2));
-    cpu.sp -= 2;
-    4
+fn push_rr(reg1: Register, reg2: Register, cpu: &mut CPU, bus: &mut impl Bus) {
+    // SP is decremented before the writes
+    bus.tick_inc_dec(cpu.sp);
+    bus.write(cpu.sp.wrapping_sub(1), cpu.get_reg(reg1));
+    bus.write(cpu.sp.wrapping_sub(2), cpu.get_reg(reg2));
+    cpu.sp = cpu.sp.wrapping_sub(2);
 }
 
-fn pop_rr(reg1: Register, reg2: Register, cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    cpu.set_reg(reg2, addr_space.read(cpu.sp));
-    cpu.set_reg(reg1, addr_space.read(cpu.sp + 1));
-    cpu.sp += 2;
-    3
+fn pop_rr(reg1: Register, reg2: Register, cpu: &mut CPU, bus: &mut impl Bus) {
+    cpu.set_reg(reg2, bus.read_inc_dec(cpu.sp));
+    cpu.set_reg(reg1, bus.read(cpu.sp.wrapping_add(1)));
+    cpu.sp = cpu.sp.wrapping_add(2);
 }
 
 // 8-bit arithmetic instructions
 
-fn add_a_n(n: u8, cpu: &mut CPU) -> u8 {
+fn add_a_n(n: u8, cpu: &mut CPU) {
     let (result, overflow) = cpu.a.overflowing_add(n);
     let half_carry = ((cpu.a & 0x0F) + (n & 0x0F)) > 0x0F;
 
     cpu.set_flags(result == 0, false, half_carry, overflow);
 
     cpu.a = result;
-
-    1
 }
 
-fn add_a_hl(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    add_a_n(addr_space.read(cpu.hl()), cpu);
-    2
+fn add_a_hl(cpu: &mut CPU, bus: &mut impl Bus) {
+    add_a_n(bus.read(cpu.hl()), cpu);
 }
 
-fn add_a_imm(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    add_a_n(cpu.next_instr(addr_space), cpu);
-    2
+fn add_a_imm(cpu: &mut CPU, bus: &mut impl Bus) {
+    add_a_n(cpu.next_instr(bus), cpu);
 }
 
-fn adc_a_n(n: u8, cpu: &mut CPU) -> u8 {
+fn adc_a_n(n: u8, cpu: &mut CPU) {
     let carry_bit = if cpu.carry_flag() { 1 } else { 0 };
     let result = n as u16 + cpu.a as u16 + carry_bit;
 
@@ -775,42 +821,34 @@ fn adc_a_n(n: u8, cpu: &mut CPU) -> u8 {
     );
 
     cpu.a = result as u8;
-
-    1
 }
 
-fn adc_a_hl(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    adc_a_n(addr_space.read(cpu.hl()), cpu);
-    2
+fn adc_a_hl(cpu: &mut CPU, bus: &mut impl Bus) {
+    adc_a_n(bus.read(cpu.hl()), cpu);
 }
 
-fn adc_a_imm(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    adc_a_n(cpu.next_instr(addr_space), cpu);
-    2
+fn adc_a_imm(cpu: &mut CPU, bus: &mut impl Bus) {
+    adc_a_n(cpu.next_instr(bus), cpu);
 }
 
-fn sub_a_n(n: u8, cpu: &mut CPU) -> u8 {
+fn sub_a_n(n: u8, cpu: &mut CPU) {
     let (result, overflow) = cpu.a.overflowing_sub(n);
     let half_carry = (n & 0x0F) > (cpu.a & 0x0F);
 
     cpu.set_flags(result == 0, true, half_carry, overflow);
 
     cpu.a = result;
-
-    1
 }
 
-fn sub_a_hl(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    sub_a_n(addr_space.read(cpu.hl()), cpu);
-    2
+fn sub_a_hl(cpu: &mut CPU, bus: &mut impl Bus) {
+    sub_a_n(bus.read(cpu.hl()), cpu);
 }
 
-fn sub_a_imm(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    sub_a_n(cpu.next_instr(addr_space), cpu);
-    2
+fn sub_a_imm(cpu: &mut CPU, bus: &mut impl Bus) {
+    sub_a_n(cpu.next_instr(bus), cpu);
 }
 
-fn sbc_a_n(n: u8, cpu: &mut CPU) -> u8 {
+fn sbc_a_n(n: u8, cpu: &mut CPU) {
     let carry_bit = if cpu.carry_flag() { 1 } else { 0 };
     let result = cpu.a as i16 - n as i16 - carry_bit;
 
@@ -822,82 +860,66 @@ fn sbc_a_n(n: u8, cpu: &mut CPU) -> u8 {
     );
 
     cpu.a = result as u8;
-
-    1
 }
 
-fn sbc_a_hl(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    sbc_a_n(addr_space.read(cpu.hl()), cpu);
-    2
+fn sbc_a_hl(cpu: &mut CPU, bus: &mut impl Bus) {
+    sbc_a_n(bus.read(cpu.hl()), cpu);
 }
 
-fn sbc_a_imm(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    sbc_a_n(cpu.next_instr(addr_space), cpu);
-    2
+fn sbc_a_imm(cpu: &mut CPU, bus: &mut impl Bus) {
+    sbc_a_n(cpu.next_instr(bus), cpu);
 }
 
-fn and_a_n(n: u8, cpu: &mut CPU) -> u8 {
+fn and_a_n(n: u8, cpu: &mut CPU) {
     cpu.a = cpu.a & n;
     cpu.set_flags(cpu.a == 0, false, true, false);
-    1
 }
 
-fn and_a_hl(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    and_a_n(addr_space.read(cpu.hl()), cpu);
-    2
+fn and_a_hl(cpu: &mut CPU, bus: &mut impl Bus) {
+    and_a_n(bus.read(cpu.hl()), cpu);
 }
 
-fn and_a_imm(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    and_a_n(cpu.next_instr(addr_space), cpu);
-    2
+fn and_a_imm(cpu: &mut CPU, bus: &mut impl Bus) {
+    and_a_n(cpu.next_instr(bus), cpu);
 }
 
-fn or_a_n(n: u8, cpu: &mut CPU) -> u8 {
+fn or_a_n(n: u8, cpu: &mut CPU) {
     cpu.a = cpu.a | n;
     cpu.set_flags(cpu.a == 0, false, false, false);
-    1
 }
 
-fn or_a_hl(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    or_a_n(addr_space.read(cpu.hl()), cpu);
-    2
+fn or_a_hl(cpu: &mut CPU, bus: &mut impl Bus) {
+    or_a_n(bus.read(cpu.hl()), cpu);
 }
 
-fn or_a_imm(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    or_a_n(cpu.next_instr(addr_space), cpu);
-    2
+fn or_a_imm(cpu: &mut CPU, bus: &mut impl Bus) {
+    or_a_n(cpu.next_instr(bus), cpu);
 }
 
-fn xor_a_n(n: u8, cpu: &mut CPU) -> u8 {
+fn xor_a_n(n: u8, cpu: &mut CPU) {
     cpu.a = cpu.a ^ n;
     cpu.set_flags(cpu.a == 0, false, false, false);
-    1
 }
 
-fn xor_a_hl(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    xor_a_n(addr_space.read(cpu.hl()), cpu);
-    2
+fn xor_a_hl(cpu: &mut CPU, bus: &mut impl Bus) {
+    xor_a_n(bus.read(cpu.hl()), cpu);
 }
 
-fn xor_a_imm(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    xor_a_n(cpu.next_instr(addr_space), cpu);
-    2
+fn xor_a_imm(cpu: &mut CPU, bus: &mut impl Bus) {
+    xor_a_n(cpu.next_instr(bus), cpu);
 }
 
-fn cp_a_n(n: u8, cpu: &mut CPU) -> u8 {
+fn cp_a_n(n: u8, cpu: &mut CPU) {
     let half_carry = (cpu.a & 0xf) < (n & 0xf);
     cpu.set_flags(cpu.a == n, true, half_carry, n > cpu.a);
-    1
 }
 
-fn cp_a_hl(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    cp_a_n(addr_space.read(cpu.hl()), cpu);
-    2
+fn cp_a_hl(cpu: &mut CPU, bus: &mut impl Bus) {
+    cp_a_n(bus.read(cpu.hl()), cpu);
 }
 
-fn cp_a_imm(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    cp_a_n(cpu.next_instr(addr_space), cpu);
-    2
+fn cp_a_imm(cpu: &mut CPU, bus: &mut impl Bus) {
+    cp_a_n(cpu.next_instr(bus), cpu);
 }
 
 fn inc_n(n: u8, cpu: &mut CPU) -> u8 {
@@ -906,16 +928,14 @@ fn inc_n(n: u8, cpu: &mut CPU) -> u8 {
     result
 }
 
-fn inc_r(register: Register, cpu: &mut CPU) -> u8 {
+fn inc_r(register: Register, cpu: &mut CPU) {
     let result = inc_n(cpu.get_reg(register), cpu);
     cpu.set_reg(register, result);
-    1
 }
 
-fn inc_hl(cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
-    let result = inc_n(addr_space.read(cpu.hl()), cpu);
-    addr_space.write(cpu.hl(), result);
-    3
+fn inc_hl(cpu: &mut CPU, bus: &mut impl Bus) {
+    let result = inc_n(bus.read(cpu.hl()), cpu);
+    bus.write(cpu.hl(), result);
 }
 
 fn dec_n(n: u8, cpu: &mut CPU) -> u8 {
@@ -924,26 +944,24 @@ fn dec_n(n: u8, cpu: &mut CPU) -> u8 {
     result
 }
 
-fn dec_r(register: Register, cpu: &mut CPU) -> u8 {
+fn dec_r(register: Register, cpu: &mut CPU) {
     let result = dec_n(cpu.get_reg(register), cpu);
     cpu.set_reg(register, result);
-    1
 }
 
-fn dec_hl(cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
-    let result = dec_n(addr_space.read(cpu.hl()), cpu);
-    addr_space.write(cpu.hl(), result);
-    3
+fn dec_hl(cpu: &mut CPU, bus: &mut impl Bus) {
+    let result = dec_n(bus.read(cpu.hl()), cpu);
+    bus.write(cpu.hl(), result);
 }
 
 // 16-bit arithmetic instructions
 
-fn add_hl_nn(nn: u16, cpu: &mut CPU) -> u8 {
+fn add_hl_nn(nn: u16, cpu: &mut CPU, bus: &mut impl Bus) {
+    bus.tick();
     let (result, overflow) = cpu.hl().overflowing_add(nn);
     let half_carry = (cpu.hl() & 0x0FFF).overflowing_add(nn & 0x0FFF).0 > 0x0FFF;
     cpu.set_flags(cpu.zero_flag(), false, half_carry, overflow);
     cpu.set_reg16(Register16::HL, result);
-    2
 }
 
 fn add_sp_n(n: i8, cpu: &mut CPU) {
@@ -957,22 +975,23 @@ fn add_sp_n(n: i8, cpu: &mut CPU) {
     cpu.set_reg16(Register16::SP, result as u16);
 }
 
-fn add_sp_imm(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    let n = cpu.next_instr(addr_space) as i8;
+fn add_sp_imm(cpu: &mut CPU, bus: &mut impl Bus) {
+    let n = cpu.next_instr(bus) as i8;
     add_sp_n(n, cpu);
-    4
+    bus.tick();
+    bus.tick();
 }
 
-fn inc_rr(register: Register16, cpu: &mut CPU) -> u8 {
+fn inc_rr(register: Register16, cpu: &mut CPU, bus: &mut impl Bus) {
+    bus.tick_inc_dec(cpu.get_reg16(register));
     let value = cpu.get_reg16(register).wrapping_add(1);
     cpu.set_reg16(register, value);
-    2
 }
 
-fn dec_rr(register: Register16, cpu: &mut CPU) -> u8 {
+fn dec_rr(register: Register16, cpu: &mut CPU, bus: &mut impl Bus) {
+    bus.tick_inc_dec(cpu.get_reg16(register));
     let value = cpu.get_reg16(register).wrapping_sub(1);
     cpu.set_reg16(register, value);
-    2
 }
 
 // Miscellaneous
@@ -983,100 +1002,97 @@ fn swap(value: u8, cpu: &mut CPU) -> u8 {
     result
 }
 
-fn swap_r(register: Register, cpu: &mut CPU) -> u8 {
+fn swap_r(register: Register, cpu: &mut CPU) {
     let result = swap(cpu.get_reg(register), cpu);
     cpu.set_reg(register, result);
-    2
 }
 
-fn swap_hl(cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
-    addr_space.write(cpu.hl(), swap(addr_space.read(cpu.hl()), cpu));
-    4
+fn swap_hl(cpu: &mut CPU, bus: &mut impl Bus) {
+    let value = swap(bus.read(cpu.hl()), cpu);
+    bus.write(cpu.hl(), value);
 }
 
-fn jp_nn(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    let nn = (cpu.next_instr(addr_space) as u16) | ((cpu.next_instr(addr_space) as u16) << 8);
+fn jp_nn(cpu: &mut CPU, bus: &mut impl Bus) {
+    let nn = (cpu.next_instr(bus) as u16) | ((cpu.next_instr(bus) as u16) << 8);
     cpu.pc = nn;
-    4
+    bus.tick();
 }
 
-fn jr_e(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    let e = cpu.next_instr(addr_space);
+fn jr_e(cpu: &mut CPU, bus: &mut impl Bus) {
+    let e = cpu.next_instr(bus);
     let offset = e as i8;
     cpu.pc = cpu.pc.wrapping_add_signed(offset as i16);
-    3
+    bus.tick();
 }
 
-fn jp_hl(cpu: &mut CPU) -> u8 {
+fn jp_hl(cpu: &mut CPU) {
     cpu.pc = cpu.hl();
-    1
 }
 
-fn jr_cc_e(cc: bool, cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    let offset = cpu.next_instr(addr_space) as i8;
+fn jr_cc_e(cc: bool, cpu: &mut CPU, bus: &mut impl Bus) {
+    let offset = cpu.next_instr(bus) as i8;
     if cc {
         cpu.pc = cpu.pc.wrapping_add_signed(offset as i16);
-        3
-    } else {
-        2
+        bus.tick();
     }
 }
 
-fn jp_cc_nn(cc: bool, cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    let n1 = cpu.next_instr(addr_space);
-    let n2 = cpu.next_instr(addr_space);
+fn jp_cc_nn(cc: bool, cpu: &mut CPU, bus: &mut impl Bus) {
+    let n1 = cpu.next_instr(bus);
+    let n2 = cpu.next_instr(bus);
     if cc {
         cpu.pc = (n1 as u16) | ((n2 as u16) << 8);
-        3
-    } else {
-        2
+        bus.tick();
     }
 }
 
-fn call_cc_nn(cc: bool, cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
-    let n1 = cpu.next_instr(addr_space);
-    let n2 = cpu.next_instr(addr_space);
+fn call_cc_nn(cc: bool, cpu: &mut CPU, bus: &mut impl Bus) {
+    let n1 = cpu.next_instr(bus);
+    let n2 = cpu.next_instr(bus);
     if cc {
+        bus.tick_inc_dec(cpu.sp);
         cpu.sp = cpu.sp.wrapping_sub(1);
-        addr_space.write(cpu.sp, ((cpu.pc & 0xff00) >> 8) as u8);
+        bus.write(cpu.sp, ((cpu.pc & 0xff00) >> 8) as u8);
         cpu.sp = cpu.sp.wrapping_sub(1);
-        addr_space.write(cpu.sp, (cpu.pc & 0xff) as u8);
+        bus.write(cpu.sp, (cpu.pc & 0xff) as u8);
         cpu.pc = (n1 as u16) | ((n2 as u16) << 8);
-        6
-    } else {
-        3
     }
 }
 
-fn di(cpu: &mut CPU) -> u8 {
+fn di(cpu: &mut CPU) {
     cpu.ime = false;
-    1
+    cpu.schedule_ime = false;
 }
 
-fn ret_cc(cc: bool, cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
+fn ret(cpu: &mut CPU, bus: &mut impl Bus) {
+    let lsb = bus.read_inc_dec(cpu.sp);
+    let msb = bus.read(cpu.sp.wrapping_add(1));
+    cpu.sp = cpu.sp.wrapping_add(2);
+    cpu.pc = (lsb as u16) | ((msb as u16) << 8);
+    bus.tick();
+}
+
+// Unlike RET, the conditional version spends an extra M-cycle checking the flag
+fn ret_cc(cc: bool, cpu: &mut CPU, bus: &mut impl Bus) {
+    bus.tick();
     if cc {
-        let lsb = addr_space.read(cpu.sp);
-        let msb = addr_space.read(cpu.sp.wrapping_add(1));
-        cpu.sp = cpu.sp.wrapping_add(2);
-        cpu.pc = (lsb as u16) | ((msb as u16) << 8);
+        ret(cpu, bus);
     }
-    2
 }
 
-fn reti(cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    ret_cc(true, cpu, addr_space);
-    cpu.schedule_ime = true;
-    2
+fn reti(cpu: &mut CPU, bus: &mut impl Bus) {
+    ret(cpu, bus);
+    // Unlike EI there's no delay
+    cpu.ime = true;
 }
 
-fn set_b_r(b: u32, register: Register, cpu: &mut CPU) -> u8 {
+fn set_b_r(b: u32, register: Register, cpu: &mut CPU) {
     cpu.set_reg(register, cpu.get_reg(register) | 2_u8.pow(b));
-    2
 }
 
-fn set_n_hl(n: u32, cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
-    addr_space.write(cpu.hl(), addr_space.read(cpu.hl()) | 2_u8.pow(n));
-    4
+fn set_n_hl(n: u32, cpu: &mut CPU, bus: &mut impl Bus) {
+    let value = bus.read(cpu.hl()) | 2_u8.pow(n);
+    bus.write(cpu.hl(), value);
 }
 
 fn bit_n(value: u8, n: u32, cpu: &mut CPU) {
@@ -1084,14 +1100,12 @@ fn bit_n(value: u8, n: u32, cpu: &mut CPU) {
     cpu.set_flags(value & b == 0, false, true, cpu.carry_flag());
 }
 
-fn bit_n_r(n: u32, register: Register, cpu: &mut CPU) -> u8 {
+fn bit_n_r(n: u32, register: Register, cpu: &mut CPU) {
     bit_n(cpu.get_reg(register), n, cpu);
-    2
 }
 
-fn bit_n_hl(n: u32, cpu: &mut CPU, addr_space: &AddrSpace) -> u8 {
-    bit_n(addr_space.read(cpu.hl()), n, cpu);
-    4
+fn bit_n_hl(n: u32, cpu: &mut CPU, bus: &mut impl Bus) {
+    bit_n(bus.read(cpu.hl()), n, cpu);
 }
 
 fn rl(value: u8, cpu: &mut CPU) -> u8 {
@@ -1101,23 +1115,20 @@ fn rl(value: u8, cpu: &mut CPU) -> u8 {
     result
 }
 
-fn rl_r(register: Register, cpu: &mut CPU) -> u8 {
+fn rl_r(register: Register, cpu: &mut CPU) {
     let result = rl(cpu.get_reg(register), cpu);
     cpu.set_reg(register, result);
-    2
 }
 
-fn rl_hl(cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
-    let result = rl(addr_space.read(cpu.hl()), cpu);
-    addr_space.write(cpu.hl(), result);
-    4
+fn rl_hl(cpu: &mut CPU, bus: &mut impl Bus) {
+    let result = rl(bus.read(cpu.hl()), cpu);
+    bus.write(cpu.hl(), result);
 }
 
-fn rla(cpu: &mut CPU) -> u8 {
+fn rla(cpu: &mut CPU) {
     let result = rl(cpu.get_reg(Register::A), cpu);
     cpu.set_reg(Register::A, result);
     cpu.set_flags(false, false, false, cpu.carry_flag());
-    2
 }
 
 fn rlc(value: u8, cpu: &mut CPU) -> u8 {
@@ -1127,23 +1138,20 @@ fn rlc(value: u8, cpu: &mut CPU) -> u8 {
     result
 }
 
-fn rlc_r(register: Register, cpu: &mut CPU) -> u8 {
+fn rlc_r(register: Register, cpu: &mut CPU) {
     let result = rlc(cpu.get_reg(register), cpu);
     cpu.set_reg(register, result);
-    2
 }
 
-fn rlc_hl(cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
-    let result = rlc(addr_space.read(cpu.hl()), cpu);
-    addr_space.write(cpu.hl(), result);
-    4
+fn rlc_hl(cpu: &mut CPU, bus: &mut impl Bus) {
+    let result = rlc(bus.read(cpu.hl()), cpu);
+    bus.write(cpu.hl(), result);
 }
 
-fn rlca(cpu: &mut CPU) -> u8 {
+fn rlca(cpu: &mut CPU) {
     let result = rlc(cpu.get_reg(Register::A), cpu);
     cpu.set_reg(Register::A, result);
     cpu.set_flags(false, false, false, cpu.carry_flag());
-    2
 }
 
 fn rrc(value: u8, cpu: &mut CPU) -> u8 {
@@ -1153,23 +1161,20 @@ fn rrc(value: u8, cpu: &mut CPU) -> u8 {
     result
 }
 
-fn rrc_r(register: Register, cpu: &mut CPU) -> u8 {
+fn rrc_r(register: Register, cpu: &mut CPU) {
     let result = rrc(cpu.get_reg(register), cpu);
     cpu.set_reg(register, result);
-    2
 }
 
-fn rrc_hl(cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
-    let result = rrc(addr_space.read(cpu.hl()), cpu);
-    addr_space.write(cpu.hl(), result);
-    4
+fn rrc_hl(cpu: &mut CPU, bus: &mut impl Bus) {
+    let result = rrc(bus.read(cpu.hl()), cpu);
+    bus.write(cpu.hl(), result);
 }
 
-fn rrca(cpu: &mut CPU) -> u8 {
+fn rrca(cpu: &mut CPU) {
     let result = rrc(cpu.get_reg(Register::A), cpu);
     cpu.set_reg(Register::A, result);
     cpu.set_flags(false, false, false, cpu.carry_flag());
-    2
 }
 
 fn srl(value: u8, cpu: &mut CPU) -> u8 {
@@ -1178,16 +1183,14 @@ fn srl(value: u8, cpu: &mut CPU) -> u8 {
     result
 }
 
-fn srl_r(register: Register, cpu: &mut CPU) -> u8 {
+fn srl_r(register: Register, cpu: &mut CPU) {
     let result = srl(cpu.get_reg(register), cpu);
     cpu.set_reg(register, result);
-    2
 }
 
-fn srl_hl(cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
-    let result = srl(addr_space.read(cpu.hl()), cpu);
-    addr_space.write(cpu.hl(), result);
-    4
+fn srl_hl(cpu: &mut CPU, bus: &mut impl Bus) {
+    let result = srl(bus.read(cpu.hl()), cpu);
+    bus.write(cpu.hl(), result);
 }
 
 fn rr(value: u8, cpu: &mut CPU) -> u8 {
@@ -1197,27 +1200,24 @@ fn rr(value: u8, cpu: &mut CPU) -> u8 {
     result
 }
 
-fn rr_r(register: Register, cpu: &mut CPU) -> u8 {
+fn rr_r(register: Register, cpu: &mut CPU) {
     let result = rr(cpu.get_reg(register), cpu);
     cpu.set_reg(register, result);
-    2
 }
 
-fn rr_hl(cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
-    let result = rr(addr_space.read(cpu.hl()), cpu);
-    addr_space.write(cpu.hl(), result);
-    4
+fn rr_hl(cpu: &mut CPU, bus: &mut impl Bus) {
+    let result = rr(bus.read(cpu.hl()), cpu);
+    bus.write(cpu.hl(), result);
 }
 
-fn rra(cpu: &mut CPU) -> u8 {
+fn rra(cpu: &mut CPU) {
     let result = rr(cpu.get_reg(Register::A), cpu);
     cpu.set_reg(Register::A, result);
     cpu.set_flags(false, false, false, cpu.carry_flag());
-    2
 }
 
-fn ldhl_sp_n(cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
-    let n = cpu.next_instr(addr_space) as i8;
+fn ldhl_sp_n(cpu: &mut CPU, bus: &mut impl Bus) {
+    let n = cpu.next_instr(bus) as i8;
     let result = cpu.sp as i32 + n as i32;
     cpu.set_flags(
         false,
@@ -1226,27 +1226,25 @@ fn ldhl_sp_n(cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
         (((cpu.sp as i16) ^ (n as i16) ^ ((result & 0xffff) as i16)) & 0x100) == 0x100,
     );
     cpu.set_reg16(Register16::HL, result as u16);
-    3
+    bus.tick();
 }
 
-fn ei(cpu: &mut CPU) -> u8 {
+fn ei(cpu: &mut CPU) {
     cpu.schedule_ime = true;
-    2
 }
 
-fn cpl(cpu: &mut CPU) -> u8 {
+fn cpl(cpu: &mut CPU) {
     cpu.a = !cpu.a;
     cpu.set_flags(cpu.zero_flag(), true, true, cpu.carry_flag());
-    1
 }
 
-fn rst_n(n: u16, cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
+fn rst_n(n: u16, cpu: &mut CPU, bus: &mut impl Bus) {
+    bus.tick_inc_dec(cpu.sp);
     cpu.sp = cpu.sp.wrapping_sub(1);
-    addr_space.write(cpu.sp, ((cpu.pc & 0xff00) >> 8) as u8);
+    bus.write(cpu.sp, ((cpu.pc & 0xff00) >> 8) as u8);
     cpu.sp = cpu.sp.wrapping_sub(1);
-    addr_space.write(cpu.sp, (cpu.pc & 0xff) as u8);
+    bus.write(cpu.sp, (cpu.pc & 0xff) as u8);
     cpu.pc = n;
-    8
 }
 
 fn sla(value: u8, cpu: &mut CPU) -> u8 {
@@ -1256,16 +1254,14 @@ fn sla(value: u8, cpu: &mut CPU) -> u8 {
     result
 }
 
-fn sla_r(register: Register, cpu: &mut CPU) -> u8 {
+fn sla_r(register: Register, cpu: &mut CPU) {
     let result = sla(cpu.get_reg(register), cpu);
     cpu.set_reg(register, result);
-    2
 }
 
-fn sla_hl(cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
-    let result = sla(addr_space.read(cpu.hl()), cpu);
-    addr_space.write(cpu.hl(), result);
-    2
+fn sla_hl(cpu: &mut CPU, bus: &mut impl Bus) {
+    let result = sla(bus.read(cpu.hl()), cpu);
+    bus.write(cpu.hl(), result);
 }
 
 fn sra(value: u8, cpu: &mut CPU) -> u8 {
@@ -1275,49 +1271,60 @@ fn sra(value: u8, cpu: &mut CPU) -> u8 {
     result
 }
 
-fn sra_r(register: Register, cpu: &mut CPU) -> u8 {
+fn sra_r(register: Register, cpu: &mut CPU) {
     let result = sra(cpu.get_reg(register), cpu);
     cpu.set_reg(register, result);
-    2
 }
 
-fn sra_hl(cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
-    let result = sra(addr_space.read(cpu.hl()), cpu);
-    addr_space.write(cpu.hl(), result);
-    2
+fn sra_hl(cpu: &mut CPU, bus: &mut impl Bus) {
+    let result = sra(bus.read(cpu.hl()), cpu);
+    bus.write(cpu.hl(), result);
 }
 
-fn scf(cpu: &mut CPU) -> u8 {
+fn scf(cpu: &mut CPU) {
     cpu.set_flags(cpu.zero_flag(), false, false, true);
-    1
 }
 
-fn ccf(cpu: &mut CPU) -> u8 {
+fn ccf(cpu: &mut CPU) {
     cpu.set_flags(cpu.zero_flag(), false, false, !cpu.carry_flag());
-    1
 }
 
-fn stop() -> u8 {
-    //TODO: How to handle this?
-    1
+// Undefined opcodes hang the CPU
+fn lock(opcode: u8, cpu: &mut CPU) {
+    cpu.locked = true;
+    cpu.event = Some(Event::Locked {
+        pc: cpu.pc.wrapping_sub(1),
+        opcode,
+    });
 }
 
-fn halt() -> u8 {
-    //TODO: How to handle this?
-    1
+fn stop(cpu: &mut CPU, bus: &mut impl Bus) {
+    cpu.stopped = true;
+    bus.stop_timer(true);
 }
 
-fn res_b_r(b: u32, register: Register, cpu: &mut CPU) -> u8 {
+fn halt(cpu: &mut CPU, bus: &mut impl Bus) {
+    if bus.pending_interrupts() == 0 {
+        cpu.halted = true;
+    } else if cpu.schedule_ime {
+        // Right after EI the interrupt is serviced with HALT itself as the return address,
+        // so HALT runs again once the handler returns
+        cpu.pc = cpu.pc.wrapping_sub(1);
+    } else if !cpu.ime {
+        cpu.halt_bug = true;
+    }
+}
+
+fn res_b_r(b: u32, register: Register, cpu: &mut CPU) {
     cpu.set_reg(register, cpu.get_reg(register) & !(2_u8.pow(b)));
-    2
 }
 
-fn res_b_hl(b: u32, cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
-    addr_space.write(cpu.hl(), addr_space.read(cpu.hl()) & !(2_u8.pow(b)));
-    2
+fn res_b_hl(b: u32, cpu: &mut CPU, bus: &mut impl Bus) {
+    let value = bus.read(cpu.hl()) & !(2_u8.pow(b));
+    bus.write(cpu.hl(), value);
 }
 
-fn daa(cpu: &mut CPU) -> u8 {
+fn daa(cpu: &mut CPU) {
     let mut add = 0;
     let mut carry = false;
 
@@ -1337,6 +1344,4 @@ fn daa(cpu: &mut CPU) -> u8 {
     }
 
     cpu.set_flags(cpu.a == 0, cpu.sub_flag(), false, carry);
-
-    1
 }
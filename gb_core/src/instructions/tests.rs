@@ -60,10 +60,11 @@ fn test_rr_r() {
 #[test]
 fn test_add_hl_nn() {
     let mut cpu = CPU::new();
+    let mut bus = TraceBus::new(&[]);
 
     cpu.set_reg16(Register16::HL, 0xFFFF);
 
-    add_hl_nn(0x10, &mut cpu);
+    add_hl_nn(0x10, &mut cpu, &mut bus);
 
     assert_eq!(cpu.hl(), 0x000F);
     assert!(cpu.half_carry_flag());
@@ -71,7 +72,7 @@ fn test_add_hl_nn() {
 
     cpu.set_reg16(Register16::HL, 0x0FFF);
 
-    add_hl_nn(0x10, &mut cpu);
+    add_hl_nn(0x10, &mut cpu, &mut bus);
 
     assert_eq!(cpu.hl(), 0x100F);
     assert!(cpu.half_carry_flag());
@@ -79,7 +80,7 @@ fn test_add_hl_nn() {
 
     cpu.set_reg16(Register16::HL, 0x00FF);
 
-    add_hl_nn(0x10, &mut cpu);
+    add_hl_nn(0x10, &mut cpu, &mut bus);
 
     assert_eq!(cpu.hl(), 0x010F);
     assert!(!cpu.half_carry_flag());
@@ -129,4 +130,353 @@ fn test_xor_n() {
 
     assert_eq!(cpu.a, 0xFF);
     assert!(!cpu.zero_flag());
-}
\ No newline at end of file
+}
+#[derive(Debug, PartialEq)]
+enum Access {
+    Read(u16),
+    Write(u16, u8),
+    Idle,
+}
+
+// Flat 64K memory that records what the CPU does on every M-cycle
+struct TraceBus {
+    memory: Vec<u8>,
+    trace: Vec<Access>,
+}
+
+impl TraceBus {
+    fn new(program: &[u8]) -> Self {
+        let mut memory = vec![0; 0x10000];
+        memory[..program.len()].copy_from_slice(program);
+        TraceBus {
+            memory,
+            trace: Vec::new(),
+        }
+    }
+}
+
+impl Bus for TraceBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.trace.push(Access::Read(addr));
+        self.memory[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.trace.push(Access::Write(addr, data));
+        self.memory[addr as usize] = data;
+    }
+
+    fn tick(&mut self) {
+        self.trace.push(Access::Idle);
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.memory[0xFFFF] & self.memory[0xFF0F] & 0x1F
+    }
+
+    fn acknowledge_interrupt(&mut self, bit: u8) {
+        self.memory[0xFF0F] &= !(1 << bit);
+    }
+
+    fn joypad_lines(&self) -> u8 {
+        self.memory[0xFF00] & 0x0F
+    }
+}
+
+fn run_one(program: &[u8], cpu: &mut CPU) -> Vec<Access> {
+    let mut bus = TraceBus::new(program);
+    let instr = cpu.next_instr(&mut bus);
+    exec_instruction(instr, cpu, &mut bus);
+    bus.trace
+}
+
+#[test]
+fn test_memory_access_timing() {
+    use Access::*;
+
+    // PUSH BC: the pushes come after an internal M-cycle
+    let mut cpu = CPU::new();
+    cpu.pc = 0;
+    cpu.sp = 0xD000;
+    cpu.set_reg16(Register16::BC, 0x1234);
+    assert_eq!(
+        run_one(&[0xC5], &mut cpu),
+        vec![Read(0), Idle, Write(0xCFFF, 0x12), Write(0xCFFE, 0x34)]
+    );
+
+    // CALL nn
+    let mut cpu = CPU::new();
+    cpu.pc = 0;
+    cpu.sp = 0xD000;
+    assert_eq!(
+        run_one(&[0xCD, 0x00, 0x40], &mut cpu),
+        vec![
+            Read(0),
+            Read(1),
+            Read(2),
+            Idle,
+            Write(0xCFFF, 0x00),
+            Write(0xCFFE, 0x03)
+        ]
+    );
+    assert_eq!(cpu.pc, 0x4000);
+
+    // RET NZ not taken still spends a cycle on the condition
+    let mut cpu = CPU::new();
+    cpu.pc = 0;
+    cpu.set_flags(true, false, false, false);
+    assert_eq!(run_one(&[0xC0], &mut cpu), vec![Read(0), Idle]);
+
+    // INC (HL) reads then writes back
+    let mut cpu = CPU::new();
+    cpu.pc = 0;
+    cpu.set_reg16(Register16::HL, 0x0001);
+    assert_eq!(
+        run_one(&[0x34, 0x41], &mut cpu),
+        vec![Read(0), Read(1), Write(1, 0x42)]
+    );
+}
+
+#[test]
+fn test_pop_wraps_around() {
+    use Access::*;
+
+    // POP BC with SP at the top of memory reads FFFF then 0000
+    let mut cpu = CPU::new();
+    cpu.pc = 0;
+    cpu.sp = 0xFFFF;
+    assert_eq!(
+        run_one(&[0xC1], &mut cpu),
+        vec![Read(0), Read(0xFFFF), Read(0x0000)]
+    );
+    assert_eq!(cpu.sp, 0x0001);
+    assert_eq!(cpu.bc(), 0xC100);
+}
+
+// Expected timings from Blargg's instr_timing, 0 marks the opcodes it doesn't time
+#[rustfmt::skip]
+const INSTR_TIMING: [u8; 256] = [
+    1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1,
+    0, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1,
+    2, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+    2, 3, 2, 2, 3, 3, 3, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    2, 2, 2, 2, 2, 2, 0, 2, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    2, 3, 3, 4, 3, 4, 2, 4, 2, 4, 3, 0, 3, 6, 2, 4,
+    2, 3, 3, 0, 3, 4, 2, 4, 2, 4, 3, 0, 3, 0, 2, 4,
+    3, 3, 2, 0, 0, 4, 2, 4, 4, 1, 4, 0, 0, 0, 2, 4,
+    3, 3, 2, 1, 0, 4, 2, 4, 3, 2, 4, 1, 0, 0, 2, 4,
+];
+
+#[rustfmt::skip]
+const INSTR_TIMING_TAKEN: [u8; 256] = [
+    1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1,
+    0, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1,
+    3, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1,
+    3, 3, 2, 2, 3, 3, 3, 1, 3, 2, 2, 2, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    2, 2, 2, 2, 2, 2, 0, 2, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+    5, 3, 4, 4, 6, 4, 2, 4, 5, 4, 4, 0, 6, 6, 2, 4,
+    5, 3, 4, 0, 6, 4, 2, 4, 5, 4, 4, 0, 6, 0, 2, 4,
+    3, 3, 2, 0, 0, 4, 2, 4, 4, 1, 4, 0, 0, 0, 2, 4,
+    3, 3, 2, 1, 0, 4, 2, 4, 3, 2, 4, 1, 0, 0, 2, 4,
+];
+
+#[rustfmt::skip]
+const INSTR_TIMING_CB: [u8; 256] = [
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+    2, 2, 2, 2, 2, 2, 3, 2, 2, 2, 2, 2, 2, 2, 3, 2,
+    2, 2, 2, 2, 2, 2, 3, 2, 2, 2, 2, 2, 2, 2, 3, 2,
+    2, 2, 2, 2, 2, 2, 3, 2, 2, 2, 2, 2, 2, 2, 3, 2,
+    2, 2, 2, 2, 2, 2, 3, 2, 2, 2, 2, 2, 2, 2, 3, 2,
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+    2, 2, 2, 2, 2, 2, 4, 2, 2, 2, 2, 2, 2, 2, 4, 2,
+];
+
+// Runs the instruction and returns (M-cycles seen on the bus, M-cycles reported)
+fn timed_run(program: &[u8], flags: u8) -> (usize, u8) {
+    let mut cpu = CPU::new();
+    cpu.pc = 0;
+    cpu.sp = 0xD000;
+    cpu.set_reg16(Register16::BC, 0xC000);
+    cpu.set_reg16(Register16::DE, 0xC000);
+    cpu.set_reg16(Register16::HL, 0xC000);
+    cpu.f = flags;
+    let mut bus = TraceBus::new(program);
+    let instr = cpu.next_instr(&mut bus);
+    let cycles = exec_instruction(instr, &mut cpu, &mut bus);
+    (bus.trace.len(), cycles)
+}
+
+// The tables must agree with instr_timing, and so must the M-cycles the handlers spend on the
+// bus, so a wrong table entry can't hide behind a handler that matches it
+#[test]
+fn test_instr_timing() {
+    for opcode in 0..=255_u8 {
+        let not_taken = INSTR_TIMING[opcode as usize];
+        if not_taken == 0 {
+            continue;
+        }
+        let taken = INSTR_TIMING_TAKEN[opcode as usize];
+        let branch = if taken != not_taken { taken } else { 0 };
+        assert_eq!(CYCLES[opcode as usize], not_taken, "opcode {:02X}", opcode);
+        assert_eq!(
+            BRANCH_CYCLES[opcode as usize], branch,
+            "opcode {:02X}",
+            opcode
+        );
+
+        // With all flags clear or all set, one run takes the branch and the other doesn't
+        let runs = [timed_run(&[opcode], 0x00), timed_run(&[opcode], 0xF0)];
+        for (bus_cycles, cycles) in runs {
+            assert_eq!(bus_cycles, cycles as usize, "opcode {:02X}", opcode);
+        }
+        let mut measured = [runs[0].0, runs[1].0];
+        measured.sort();
+        let mut expected = [not_taken as usize, taken as usize];
+        expected.sort();
+        assert_eq!(measured, expected, "opcode {:02X}", opcode);
+    }
+
+    for opcode in 0..=255_u8 {
+        let expected = INSTR_TIMING_CB[opcode as usize];
+        assert_eq!(CB_CYCLES[opcode as usize], expected, "CB {:02X}", opcode);
+        let (bus_cycles, cycles) = timed_run(&[0xCB, opcode], 0x00);
+        assert_eq!(bus_cycles, cycles as usize, "CB {:02X}", opcode);
+        assert_eq!(bus_cycles, expected as usize, "CB {:02X}", opcode);
+    }
+}
+
+// CPU at 0 with the VBlank interrupt requested and enabled, but IME clear
+fn interrupt_setup(program: &[u8]) -> (CPU, TraceBus) {
+    let mut cpu = CPU::new();
+    cpu.pc = 0;
+    cpu.sp = 0xD000;
+    let mut bus = TraceBus::new(program);
+    bus.memory[0xFFFF] = 0x01;
+    bus.memory[0xFF0F] = 0x01;
+    (cpu, bus)
+}
+
+#[test]
+fn test_ei_delay() {
+    // EI, NOP: the interrupt is serviced after the NOP
+    let (mut cpu, mut bus) = interrupt_setup(&[0xFB, 0x00, 0x00]);
+    step(&mut cpu, &mut bus);
+    assert!(!cpu.ime);
+    step(&mut cpu, &mut bus);
+    assert!(cpu.ime);
+    bus.trace.clear();
+    assert_eq!(step(&mut cpu, &mut bus), 5);
+    assert_eq!(bus.trace.len(), 5);
+    assert_eq!(cpu.pc, 0x40);
+    assert_eq!(bus.memory[0xCFFE], 0x02);
+    assert_eq!(bus.memory[0xFF0F], 0x00);
+
+    // EI, DI: IME never gets set
+    let (mut cpu, mut bus) = interrupt_setup(&[0xFB, 0xF3, 0x00]);
+    for _ in 0..3 {
+        step(&mut cpu, &mut bus);
+    }
+    assert!(!cpu.ime);
+    assert_eq!(cpu.pc, 3);
+}
+
+#[test]
+fn test_halt() {
+    // HALT waits for an interrupt to be requested
+    let (mut cpu, mut bus) = interrupt_setup(&[0x76, 0x00]);
+    bus.memory[0xFF0F] = 0x00;
+    step(&mut cpu, &mut bus);
+    assert!(cpu.halted);
+    assert_eq!(step(&mut cpu, &mut bus), 1);
+    bus.memory[0xFF0F] = 0x01;
+    step(&mut cpu, &mut bus);
+    assert!(!cpu.halted);
+    assert_eq!(cpu.pc, 2);
+
+    // EI, HALT with a pending interrupt: the handler returns to the HALT
+    let (mut cpu, mut bus) = interrupt_setup(&[0xFB, 0x76, 0x00]);
+    step(&mut cpu, &mut bus);
+    step(&mut cpu, &mut bus);
+    assert!(!cpu.halted);
+    step(&mut cpu, &mut bus);
+    assert_eq!(cpu.pc, 0x40);
+    assert_eq!(bus.memory[0xCFFE], 0x01);
+
+    // HALT bug: with IME clear the byte after HALT is read twice
+    let (mut cpu, mut bus) = interrupt_setup(&[0x76, 0x04, 0x00]);
+    step(&mut cpu, &mut bus);
+    step(&mut cpu, &mut bus);
+    step(&mut cpu, &mut bus);
+    assert_eq!(cpu.b, 2);
+    assert_eq!(cpu.pc, 2);
+}
+
+#[test]
+fn test_ie_push_cancels_dispatch() {
+    // With SP at 0 the high byte of PC is pushed into IE, disabling the VBlank interrupt
+    let (mut cpu, mut bus) = interrupt_setup(&[]);
+    cpu.ime = true;
+    cpu.sp = 0x0000;
+    cpu.pc = 0x0200;
+    step(&mut cpu, &mut bus);
+    assert_eq!(cpu.pc, 0x0000);
+    assert_eq!(bus.memory[0xFF0F], 0x01);
+
+    // Another enabled interrupt is picked instead
+    let (mut cpu, mut bus) = interrupt_setup(&[]);
+    bus.memory[0xFF0F] = 0x03;
+    cpu.ime = true;
+    cpu.sp = 0x0000;
+    cpu.pc = 0x0200;
+    step(&mut cpu, &mut bus);
+    assert_eq!(cpu.pc, 0x48);
+    assert_eq!(bus.memory[0xFF0F], 0x01);
+}
+
+#[test]
+fn test_illegal_opcode_locks() {
+    let (mut cpu, mut bus) = interrupt_setup(&[0x00, 0xD3, 0x00]);
+    cpu.ime = true;
+    bus.memory[0xFF0F] = 0x00;
+    step(&mut cpu, &mut bus);
+    step(&mut cpu, &mut bus);
+    assert!(cpu.locked);
+    assert_eq!(
+        cpu.event.take(),
+        Some(Event::Locked {
+            pc: 0x0001,
+            opcode: 0xD3
+        })
+    );
+
+    // Not even interrupts get it going again
+    bus.memory[0xFF0F] = 0x01;
+    for _ in 0..10 {
+        assert_eq!(step(&mut cpu, &mut bus), 1);
+    }
+    assert_eq!(cpu.pc, 0x0002);
+    assert_eq!(cpu.event, None);
+}
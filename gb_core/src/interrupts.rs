@@ -1,38 +1,36 @@
 
-use super::addr::*;
+use super::bus::*;
 use super::cpu::*;
 
-pub fn handle_interrupts(cpu: &mut CPU, addr_space: &mut AddrSpace) -> u32 {
-    if cpu.ime {
-        if addr_space.ie_vblank() && addr_space.if_vblank() {
-            addr_space.set_if_vblank(false);
-            handle_interrupt(0x40, cpu, addr_space) as u32
-        } else if addr_space.ie_lc_stat() && addr_space.if_lc_stat() {
-            addr_space.set_if_lc_stat(false);
-            handle_interrupt(0x48, cpu, addr_space) as u32
-        } else if addr_space.ie_timer() && addr_space.if_timer() {
-            addr_space.set_if_timer(false);
-            handle_interrupt(0x50, cpu, addr_space) as u32
-        } else if addr_space.ie_serial() && addr_space.if_serial() {
-            addr_space.set_if_serial(false);
-            handle_interrupt(0x58, cpu, addr_space) as u32
-        } else if addr_space.ie_joypad() && addr_space.if_joypad() {
-            addr_space.set_if_joypad(false);
-            handle_interrupt(0x60, cpu, addr_space) as u32
-        } else {
-            0
-        }
+// Services the highest priority pending interrupt if IME is set, returns the M-cycles taken
+pub fn handle_interrupts(cpu: &mut CPU, bus: &mut impl Bus) -> u32 {
+    if cpu.ime && bus.pending_interrupts() != 0 {
+        dispatch(cpu, bus);
+        5
     } else {
         0
     }
 }
 
-fn handle_interrupt(dest: u16, cpu: &mut CPU, addr_space: &mut AddrSpace) -> u8 {
+// Two idle M-cycles, the PC push and one more M-cycle to jump to the vector.
+// The vector is only picked after the high byte of PC is pushed: if that push overwrites IE and
+// nothing is pending anymore the dispatch is cancelled and the CPU jumps to 0x0000
+fn dispatch(cpu: &mut CPU, bus: &mut impl Bus) {
     cpu.ime = false;
-    cpu.schedule_ime = false;
-    addr_space.write(cpu.sp - 1, ((cpu.pc & 0xff00) >> 8) as u8);
-    addr_space.write(cpu.sp - 2, (cpu.pc & 0xff) as u8);
-    cpu.sp -= 2;
-    cpu.pc = dest;
-    5
-}
\ No newline at end of file
+    bus.tick();
+    bus.tick_inc_dec(cpu.sp);
+    cpu.sp = cpu.sp.wrapping_sub(1);
+    bus.write(cpu.sp, ((cpu.pc & 0xff00) >> 8) as u8);
+    let pending = bus.pending_interrupts();
+    cpu.sp = cpu.sp.wrapping_sub(1);
+    bus.write(cpu.sp, (cpu.pc & 0xff) as u8);
+    cpu.pc = if pending == 0 {
+        0x0000
+    } else {
+        // VBlank has the highest priority, joypad the lowest
+        let bit = pending.trailing_zeros() as u8;
+        bus.acknowledge_interrupt(bit);
+        0x40 + 8 * bit as u16
+    };
+    bus.tick();
+}
@@ -1,5 +1,18 @@
 use crate::addr::AddrSpace;
-use crate::consts::JOYPAD_ADDR;
+use crate::save_state::{SaveStateError, StateReader, StateWriter};
+
+// In register bit order, directions first
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
 
 pub struct JoypadState {
     pub up: bool,
@@ -26,48 +39,66 @@ impl JoypadState {
         }
     }
 
-    fn get_bitset_1(&self) -> u8 {
-        let mut bitset = 0x10;
-        if self.right {
-            bitset = bitset | 1;
-        }
-        if self.left {
-            bitset |= 1 << 1;
-        }
-        if self.up {
-            bitset |= 1 << 2;
-        }
-        if self.down {
-            bitset |= 1 << 3;
-        }
-        !bitset
+    // Directions in the low nibble and buttons in the high one, in register bit order
+    pub fn pressed(&self) -> u8 {
+        let inputs = [
+            self.right,
+            self.left,
+            self.up,
+            self.down,
+            self.a,
+            self.b,
+            self.select,
+            self.start,
+        ];
+        inputs
+            .iter()
+            .enumerate()
+            .fold(0, |acc, (bit, &pressed)| acc | ((pressed as u8) << bit))
     }
 
-    fn get_bitset_2(&self) -> u8 {
-        let mut bitset = 0x20;
-        if self.a {
-            bitset |= 1;
-        }
-        if self.b {
-            bitset |= 1 << 1;
-        }
-        if self.select {
-            bitset |= 1 << 2;
-        }
-        if self.start {
-            bitset |= 1 << 3;
-        }
-        !bitset
+    pub fn set(&mut self, button: Button, pressed: bool) {
+        let input = match button {
+            Button::Right => &mut self.right,
+            Button::Left => &mut self.left,
+            Button::Up => &mut self.up,
+            Button::Down => &mut self.down,
+            Button::A => &mut self.a,
+            Button::B => &mut self.b,
+            Button::Select => &mut self.select,
+            Button::Start => &mut self.start,
+        };
+        *input = pressed;
     }
 
     pub fn update_joypad(&self, addr_space: &mut AddrSpace) {
-        let mut joypad = addr_space.read(JOYPAD_ADDR);
-        if joypad & 0x10 == 0 {
-            joypad = self.get_bitset_1();
-        } else if joypad & 0x20 == 0 {
-            joypad = self.get_bitset_2();
+        addr_space.set_joypad_buttons(self.pressed());
+    }
+
+    // Presses the inputs set in `pressed`, see pressed
+    pub fn set_pressed(&mut self, pressed: u8) {
+        let buttons = [
+            Button::Right,
+            Button::Left,
+            Button::Up,
+            Button::Down,
+            Button::A,
+            Button::B,
+            Button::Select,
+            Button::Start,
+        ];
+        for (bit, &button) in buttons.iter().enumerate() {
+            self.set(button, pressed & (1 << bit) != 0);
         }
-        addr_space.write(JOYPAD_ADDR, joypad);
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.pressed());
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.set_pressed(state.read_u8()?);
+        Ok(())
     }
 
     pub fn reset(&mut self) {
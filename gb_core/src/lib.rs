@@ -1,10 +1,23 @@
 #![feature(mixed_integer_ops)]
 
 pub mod addr;
+pub mod bus;
+pub mod cartridge;
 pub mod consts;
 pub mod cpu;
 pub mod debug;
+pub mod device;
+pub mod dma;
+pub mod gameboy;
+pub mod hash;
 pub mod instructions;
 pub mod ppu;
 pub mod interrupts;
-pub mod joypad;
\ No newline at end of file
+pub mod joypad;
+pub mod model;
+pub mod movie;
+pub mod rewind;
+pub mod save_state;
+pub mod scheduler;
+pub mod serial;
+pub mod timer;
\ No newline at end of file
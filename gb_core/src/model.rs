@@ -0,0 +1,140 @@
+use super::cpu::CPU;
+#[cfg(test)]
+mod tests;
+
+// Game Boy hardware revisions. They run the same code but leave the boot ROM with different
+// register values, which games use to detect what they're running on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Model {
+    DMG0,
+    DMG,
+    MGB,
+    SGB,
+    SGB2,
+    CGB,
+    AGB,
+}
+
+impl Model {
+    pub fn is_cgb(&self) -> bool {
+        matches!(self, Model::CGB | Model::AGB)
+    }
+
+    pub fn is_sgb(&self) -> bool {
+        matches!(self, Model::SGB | Model::SGB2)
+    }
+
+    // Size of the model's boot ROM dump. The CGB one is mapped at 0x000-0x0FF and 0x200-0x8FF,
+    // leaving the cartridge header visible in between
+    pub fn boot_rom_size(&self) -> usize {
+        if self.is_cgb() {
+            0x900
+        } else {
+            0x100
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Model> {
+        match name.to_ascii_lowercase().as_str() {
+            "dmg0" => Some(Model::DMG0),
+            "dmg" => Some(Model::DMG),
+            "mgb" => Some(Model::MGB),
+            "sgb" => Some(Model::SGB),
+            "sgb2" => Some(Model::SGB2),
+            "cgb" => Some(Model::CGB),
+            "agb" => Some(Model::AGB),
+            _ => None,
+        }
+    }
+
+    // CPU registers when the boot ROM jumps to 0x100. On DMG and MGB the half-carry and carry
+    // flags are left set unless the header checksum at 0x14D is 0
+    pub fn post_boot_cpu(&self, header_checksum: u8) -> CPU {
+        let checksum_flags = if header_checksum == 0 { 0x80 } else { 0xB0 };
+        let (af, bc, de, hl) = match self {
+            Model::DMG0 => (0x0100, 0xFF13, 0x00C1, 0x8403),
+            Model::DMG => (0x0100 | checksum_flags, 0x0013, 0x00D8, 0x014D),
+            Model::MGB => (0xFF00 | checksum_flags, 0x0013, 0x00D8, 0x014D),
+            Model::SGB => (0x0100, 0x0014, 0x0000, 0xC060),
+            Model::SGB2 => (0xFF00, 0x0014, 0x0000, 0xC060),
+            Model::CGB => (0x1180, 0x0000, 0xFF56, 0x000D),
+            Model::AGB => (0x1100, 0x0100, 0xFF56, 0x000D),
+        };
+        let mut cpu = CPU::new();
+        cpu.a = (af >> 8) as u8;
+        cpu.f = af as u8;
+        cpu.b = (bc >> 8) as u8;
+        cpu.c = bc as u8;
+        cpu.d = (de >> 8) as u8;
+        cpu.e = de as u8;
+        cpu.h = (hl >> 8) as u8;
+        cpu.l = hl as u8;
+        cpu.sp = 0xFFFE;
+        cpu.pc = 0x0100;
+        cpu
+    }
+
+    // IO registers (FF00-FF7F) when the boot ROM jumps to 0x100. LY and STAT describe the PPU
+    // sitting on line 153 with LY already reading 0, see PPU::post_boot
+    pub fn post_boot_io(&self) -> [u8; 0x80] {
+        let mut io = [0xFF; 0x80];
+        let registers: &[(u16, u8)] = &[
+            (0xFF00, 0xCF),
+            (0xFF01, 0x00),
+            (0xFF02, if self.is_cgb() { 0x7F } else { 0x7E }),
+            (0xFF04, self.div()),
+            (0xFF05, 0x00),
+            (0xFF06, 0x00),
+            (0xFF07, 0xF8),
+            (0xFF0F, 0xE1),
+            (0xFF10, 0x80),
+            (0xFF11, 0xBF),
+            (0xFF12, 0xF3),
+            (0xFF13, 0xFF),
+            (0xFF14, 0xBF),
+            (0xFF16, 0x3F),
+            (0xFF17, 0x00),
+            (0xFF19, 0xBF),
+            (0xFF1A, 0x7F),
+            (0xFF1C, 0x9F),
+            (0xFF1E, 0xBF),
+            (0xFF21, 0x00),
+            (0xFF22, 0x00),
+            (0xFF23, 0xBF),
+            (0xFF24, 0x77),
+            (0xFF25, 0xF3),
+            (0xFF26, if self.is_sgb() { 0xF0 } else { 0xF1 }),
+            (0xFF40, 0x91),
+            (0xFF41, 0x85),
+            (0xFF42, 0x00),
+            (0xFF43, 0x00),
+            (0xFF44, 0x00),
+            (0xFF45, 0x00),
+            (0xFF46, if self.is_cgb() { 0x00 } else { 0xFF }),
+            (0xFF47, 0xFC),
+            (0xFF4A, 0x00),
+            (0xFF4B, 0x00),
+        ];
+        for &(addr, value) in registers {
+            io[(addr - 0xFF00) as usize] = value;
+        }
+        if self.is_cgb() {
+            io[0x4D] = 0x7E;
+            io[0x4F] = 0xFE;
+            io[0x56] = 0x3E;
+            io[0x70] = 0xF8;
+        }
+        // Wave RAM isn't cleared by the boot ROM, zero is as good as anything
+        io[0x30..0x40].fill(0x00);
+        io
+    }
+
+    // The divider runs during the boot ROM, so its value depends on how long the boot took
+    fn div(&self) -> u8 {
+        match self {
+            Model::DMG0 => 0x18,
+            Model::DMG | Model::MGB => 0xAB,
+            _ => 0x00,
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use super::*;
+use crate::addr::AddrSpace;
+use crate::consts::*;
+use crate::ppu::PPU;
+
+#[test]
+fn test_post_boot_registers() {
+    let cpu = Model::DMG.post_boot_cpu(0x66);
+    assert_eq!((cpu.a, cpu.f, cpu.b, cpu.c), (0x01, 0xB0, 0x00, 0x13));
+    assert_eq!((cpu.d, cpu.e, cpu.h, cpu.l), (0x00, 0xD8, 0x01, 0x4D));
+    assert_eq!((cpu.sp, cpu.pc), (0xFFFE, 0x0100));
+    assert_eq!(Model::DMG.post_boot_cpu(0x00).f, 0x80);
+
+    // Games tell a CGB apart by A
+    assert_eq!(Model::CGB.post_boot_cpu(0x66).a, 0x11);
+    assert_eq!(Model::AGB.post_boot_cpu(0x66).b, 0x01);
+    assert_eq!(Model::MGB.post_boot_cpu(0x66).a, 0xFF);
+
+    let addr_space = AddrSpace::post_boot(Model::DMG, None);
+    assert_eq!(addr_space.read(LCDC_ADDR), 0x91);
+    assert_eq!(addr_space.read(BG_PALETTE_ADDR), 0xFC);
+    assert_eq!(addr_space.read(0xFF4D), 0xFF);
+    assert_eq!(addr_space.read(0x0000), 0x00);
+    let addr_space = AddrSpace::post_boot(Model::CGB, None);
+    assert_eq!(addr_space.read(0xFF4D), 0x7E);
+}
+
+#[test]
+fn test_post_boot_ppu() {
+    // The PPU finishes line 153 and starts the next frame without blanking it
+    let mut addr_space = AddrSpace::post_boot(Model::DMG, None);
+    let mut ppu = PPU::post_boot();
+    ppu.tick(56, &mut addr_space);
+    assert_eq!(addr_space.read(LY_ADDR), 0);
+    assert_eq!(addr_space.read(LCD_STAT_ADDR) & 0x3, 2);
+    ppu.tick(456, &mut addr_space);
+    assert_eq!(addr_space.read(LY_ADDR), 1);
+}
@@ -0,0 +1,176 @@
+use super::model::Model;
+use super::save_state::{SaveStateError, StateReader, StateWriter};
+use std::fmt;
+
+// Movie file layout: MAGIC, the format version, the version of the emulator that recorded it,
+// the model, the ROM hash and whether the boot ROM was skipped. Then an optional save state the
+// movie starts from, power-on otherwise, and one byte of joypad input per frame in
+// JoypadState::pressed order. Numbers are little endian
+const MAGIC: &[u8; 4] = b"GBMV";
+pub const VERSION: u16 = 1;
+
+#[derive(Debug, PartialEq)]
+pub enum MovieError {
+    NotAMovie,
+    // Recorded by a newer build
+    UnsupportedVersion(u16),
+    WrongModel { recorded: Model, running: Model },
+    WrongRom,
+    // Recorded with the boot ROM skipped and played back with it, or the other way around
+    WrongBoot,
+    // The movie is truncated or corrupted, or its start state can't be loaded
+    State(SaveStateError),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::NotAMovie => write!(f, "Not a movie"),
+            MovieError::UnsupportedVersion(version) => write!(
+                f,
+                "Movie version {} is newer than the supported version {}",
+                version, VERSION
+            ),
+            MovieError::WrongModel { recorded, running } => write!(
+                f,
+                "Movie was recorded on a {:?}, the running model is a {:?}",
+                recorded, running
+            ),
+            MovieError::WrongRom => write!(f, "Movie was recorded with a different ROM"),
+            MovieError::WrongBoot => write!(
+                f,
+                "Movie was recorded with a different boot setting (boot ROM or fast boot)"
+            ),
+            MovieError::State(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<SaveStateError> for MovieError {
+    fn from(e: SaveStateError) -> Self {
+        MovieError::State(e)
+    }
+}
+
+// Joypad input recorded frame by frame. Since the emulation only depends on its inputs,
+// playing the movie back from the same start reproduces the recording exactly
+pub struct Movie {
+    emulator_version: String,
+    model: Model,
+    rom_hash: u64,
+    skip_boot: bool,
+    start_state: Option<Vec<u8>>,
+    inputs: Vec<u8>,
+}
+
+impl Movie {
+    pub fn new(model: Model, rom_hash: u64, skip_boot: bool, start_state: Option<Vec<u8>>) -> Self {
+        Movie {
+            emulator_version: String::from(env!("CARGO_PKG_VERSION")),
+            model,
+            rom_hash,
+            skip_boot,
+            start_state,
+            inputs: Vec::new(),
+        }
+    }
+
+    pub fn emulator_version(&self) -> &str {
+        &self.emulator_version
+    }
+
+    // The save state the movie starts from, None if it starts at power-on
+    pub fn start_state(&self) -> Option<&[u8]> {
+        self.start_state.as_deref()
+    }
+
+    pub fn frames(&self) -> usize {
+        self.inputs.len()
+    }
+
+    // Pressed inputs during the given frame, see JoypadState::pressed
+    pub fn input(&self, frame: usize) -> Option<u8> {
+        self.inputs.get(frame).copied()
+    }
+
+    pub fn push_input(&mut self, pressed: u8) {
+        self.inputs.push(pressed);
+    }
+
+    // Checks the movie can be played back on the running machine
+    pub fn check(&self, model: Model, rom_hash: u64, skip_boot: bool) -> Result<(), MovieError> {
+        if self.model != model {
+            return Err(MovieError::WrongModel {
+                recorded: self.model,
+                running: model,
+            });
+        }
+        if self.rom_hash != rom_hash {
+            return Err(MovieError::WrongRom);
+        }
+        if self.skip_boot != skip_boot {
+            return Err(MovieError::WrongBoot);
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = StateWriter::new();
+        data.write_bytes(MAGIC);
+        data.write_u16(VERSION);
+        data.write_len(self.emulator_version.len());
+        data.write_bytes(self.emulator_version.as_bytes());
+        data.write_model(self.model);
+        data.write_u64(self.rom_hash);
+        data.write_bool(self.skip_boot);
+        match &self.start_state {
+            Some(state) => {
+                data.write_bool(true);
+                data.write_len(state.len());
+                data.write_bytes(state);
+            }
+            None => data.write_bool(false),
+        }
+        data.write_len(self.inputs.len());
+        data.write_bytes(&self.inputs);
+        data.into_bytes()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Movie, MovieError> {
+        if bytes.len() < 6 || &bytes[0..4] != MAGIC {
+            return Err(MovieError::NotAMovie);
+        }
+        let mut data = StateReader::new(&bytes[4..]);
+        let version = data.read_u16()?;
+        if version == 0 {
+            return Err(MovieError::NotAMovie);
+        }
+        if version > VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        let mut emulator_version = vec![0; data.read_len()?];
+        data.read_bytes(&mut emulator_version)?;
+        let model = data.read_model()?;
+        let rom_hash = data.read_u64()?;
+        let skip_boot = data.read_bool()?;
+        let start_state = if data.read_bool()? {
+            let mut state = vec![0; data.read_len()?];
+            data.read_bytes(&mut state)?;
+            Some(state)
+        } else {
+            None
+        };
+        let mut inputs = vec![0; data.read_len()?];
+        data.read_bytes(&mut inputs)?;
+        data.finish()?;
+
+        Ok(Movie {
+            emulator_version: String::from_utf8_lossy(&emulator_version).into_owned(),
+            model,
+            rom_hash,
+            skip_boot,
+            start_state,
+            inputs,
+        })
+    }
+}
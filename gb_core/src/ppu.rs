@@ -1,11 +1,39 @@
 use super::addr::*;
 use super::consts::*;
+use super::save_state::{SaveStateError, StateReader, StateWriter};
+mod fifo;
+#[cfg(test)]
+mod tests;
+
+pub const WIDTH: usize = 160;
+pub const HEIGHT: usize = 144;
+
+// Scanline draws a whole line at the end of mode 3, Fifo runs the pixel fetcher dot by dot
+#[derive(Clone, Copy, PartialEq)]
+pub enum Renderer {
+    Scanline,
+    Fifo,
+}
 
-const WIDTH: usize = 160;
-const HEIGHT: usize = 144;
 pub struct PPU {
     total_cycles: u32,
     pub pixels: [u32; WIDTH * HEIGHT],
+    renderer: Renderer,
+    // Takes over from `renderer` when the next mode 3 starts, a line is drawn by one renderer
+    next_renderer: Renderer,
+    // Leaves the framebuffer as it is with the Scanline renderer, for frames that won't be
+    // shown. The Fifo renderer always draws, the pixels it pushes drive its timing
+    pub skip_rendering: bool,
+    fifo: fifo::Fifo,
+    // The window keeps its own line counter, which only advances on lines where it was drawn
+    window_line: u8,
+    // All the enabled STAT sources are ORed into one line, the interrupt fires on its rising edge
+    stat_line: bool,
+    lcd_on: bool,
+    // Set on the first line after the LCD is turned on, which skips the OAM scan
+    lcd_warmup: bool,
+    // The first frame after the LCD is turned on is not shown by the hardware
+    first_frame: bool,
 }
 
 impl PPU {
@@ -13,6 +41,68 @@ impl PPU {
         Self {
             total_cycles: 0,
             pixels: [0; WIDTH * HEIGHT],
+            renderer: Renderer::Scanline,
+            next_renderer: Renderer::Scanline,
+            skip_rendering: false,
+            fifo: fifo::Fifo::new(),
+            window_line: 0,
+            stat_line: false,
+            lcd_on: false,
+            lcd_warmup: false,
+            first_frame: false,
+        }
+    }
+
+    // The boot ROM hands over with the LCD on, near the end of line 153
+    pub fn post_boot() -> Self {
+        Self {
+            total_cycles: 400,
+            lcd_on: true,
+            ..Self::new()
+        }
+    }
+
+    // Switching in the middle of mode 3 would leave the Fifo renderer with a line it didn't
+    // start, so the change is applied when the next one starts
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.next_renderer = renderer;
+    }
+
+    // The renderer is a frontend setting and isn't part of the state
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u32(self.total_cycles);
+        for &pixel in self.pixels.iter() {
+            state.write_u32(pixel);
+        }
+        self.fifo.save_state(state);
+        state.write_u8(self.window_line);
+        state.write_bool(self.stat_line);
+        state.write_bool(self.lcd_on);
+        state.write_bool(self.lcd_warmup);
+        state.write_bool(self.first_frame);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.total_cycles = state.read_u32()?;
+        for pixel in self.pixels.iter_mut() {
+            *pixel = state.read_u32()?;
+        }
+        self.fifo.load_state(state)?;
+        self.window_line = state.read_u8()?;
+        self.stat_line = state.read_bool()?;
+        self.lcd_on = state.read_bool()?;
+        self.lcd_warmup = state.read_bool()?;
+        self.first_frame = state.read_bool()?;
+        Ok(())
+    }
+
+    // The OAM row the PPU is reading during the OAM scan, it moves to the next of the 20 rows
+    // every M-cycle
+    pub fn oam_row(&self, addr_space: &AddrSpace) -> Option<usize> {
+        if self.lcd_on && self.gpu_mode(addr_space) == 2 {
+            Some(self.total_cycles as usize / 4)
+        } else {
+            None
         }
     }
 
@@ -23,46 +113,194 @@ impl PPU {
 
     fn set_gpu_mode(&mut self, mode: u8, addr_space: &mut AddrSpace) {
         let stat = addr_space.read(LCD_STAT_ADDR);
-        addr_space.write(LCD_STAT_ADDR, (stat & 0xFC) | mode);
+        addr_space.set_stat((stat & 0xFC) | mode);
     }
 
     pub fn tick(&mut self, elapsed_cycles: u32, addr_space: &mut AddrSpace) -> bool {
-        self.total_cycles += elapsed_cycles;
+        let mut vblank = false;
+        let mut remaining = elapsed_cycles;
+        while remaining > 0 {
+            // Dots where nothing happens only move the dot counter
+            let idle = self.idle_dots(addr_space).min(remaining);
+            self.total_cycles += idle;
+            remaining -= idle;
+            if remaining > 0 {
+                vblank |= self.dot(addr_space);
+                remaining -= 1;
+            }
+        }
+        vblank
+    }
 
-        set_lyc_eq_ly(addr_space);
+    // Dots until the PPU changes mode or line on its own, see Scheduler
+    pub fn dots_to_next_event(&self, addr_space: &AddrSpace) -> u32 {
+        self.idle_dots(addr_space) + 1
+    }
+
+    // Number of dots before the next one that does something. In between the mode, LY and the
+    // STAT line can't change unless the CPU writes an LCD register
+    fn idle_dots(&self, addr_space: &AddrSpace) -> u32 {
+        if addr_space.lcd_written() {
+            return 0;
+        }
+        let lcd_enabled = addr_space.read(LCDC_ADDR) & 0x80 != 0;
+        if lcd_enabled != self.lcd_on {
+            return 0;
+        }
+        if !lcd_enabled {
+            return (456 * 154 - 1_u32).saturating_sub(self.total_cycles);
+        }
+        let event = match self.gpu_mode(addr_space) {
+            0 if self.lcd_warmup => 80,
+            0 => 456,
+            1 if ly(addr_space) == 153 && self.total_cycles < 4 => 4,
+            1 => 456,
+            2 => 80,
+            3 if self.renderer == Renderer::Scanline => 80 + 172,
+            _ => return 0,
+        };
+        (event - 1_u32).saturating_sub(self.total_cycles)
+    }
+
+    // Advances the PPU by a single dot. total_cycles counts the dots into the current line.
+    // Returns true when a new frame is ready to be presented
+    fn dot(&mut self, addr_space: &mut AddrSpace) -> bool {
+        addr_space.take_lcd_write();
+        if addr_space.read(LCDC_ADDR) & 0x80 == 0 {
+            return self.lcd_off_dot(addr_space);
+        }
+        if !self.lcd_on {
+            self.lcd_on = true;
+            self.lcd_warmup = true;
+            self.first_frame = true;
+            self.window_line = 0;
+            // The first line is a few dots shorter
+            self.total_cycles = 4;
+        }
+
+        self.total_cycles += 1;
+        let mut vblank = false;
 
         match self.gpu_mode(addr_space) {
-            0 if self.total_cycles >= 204 => {
+            0 if self.lcd_warmup && self.total_cycles >= 80 => {
+                self.lcd_warmup = false;
+                self.start_mode_3(addr_space);
+            }
+            0 if self.total_cycles >= 456 => {
                 self.total_cycles = 0;
 
                 inc_ly(addr_space);
                 if ly(addr_space) == 144 {
                     self.set_gpu_mode(1, addr_space);
-                    return true;
+                    self.window_line = 0;
+                    addr_space.set_if_vblank(true);
+                    if self.first_frame {
+                        self.first_frame = false;
+                        self.pixels = [0xFFFFFFFF; WIDTH * HEIGHT];
+                    }
+                    vblank = true;
                 } else {
                     self.set_gpu_mode(2, addr_space);
                 }
             }
+            // LY already reads 0 for most of line 153
+            1 if self.total_cycles == 4 && ly(addr_space) == 153 => {
+                addr_space.set_ly(0);
+            }
             1 if self.total_cycles >= 456 => {
                 self.total_cycles = 0;
-                if ly(addr_space) == 153 {
+                if ly(addr_space) == 0 {
                     self.set_gpu_mode(2, addr_space);
+                } else {
+                    inc_ly(addr_space);
                 }
-                inc_ly(addr_space);
-                addr_space.set_if_vblank(true);
             }
             2 if self.total_cycles >= 80 => {
-                self.total_cycles = 0;
-                self.set_gpu_mode(3, addr_space);
+                self.start_mode_3(addr_space);
             }
-            3 if self.total_cycles >= 172 => {
-                self.total_cycles = 0;
-                self.set_gpu_mode(0, addr_space);
-                scanline(&addr_space, &mut self.pixels);
+            3 => {
+                let done = match self.renderer {
+                    Renderer::Scanline if self.total_cycles >= 80 + 172 => {
+                        // Skipped lines still move the window line counter
+                        let drew_window = if self.skip_rendering {
+                            window_visible(addr_space)
+                        } else {
+                            scanline(addr_space, &mut self.pixels, self.window_line)
+                        };
+                        if drew_window {
+                            self.window_line += 1;
+                        }
+                        true
+                    }
+                    Renderer::Scanline => false,
+                    Renderer::Fifo => {
+                        let done = self.fifo.dot(addr_space, &mut self.pixels);
+                        if done && self.fifo.drew_window() {
+                            self.window_line += 1;
+                        }
+                        done
+                    }
+                };
+                if done {
+                    self.set_gpu_mode(0, addr_space);
+                }
             }
             _ => {}
         }
-        false
+        self.update_stat_line(addr_space);
+        vblank
+    }
+
+    fn start_mode_3(&mut self, addr_space: &mut AddrSpace) {
+        self.set_gpu_mode(3, addr_space);
+        self.renderer = self.next_renderer;
+        if self.renderer == Renderer::Fifo {
+            self.fifo.start(addr_space, self.window_line);
+        }
+    }
+
+    // While the LCD is off LY is held at 0 and the screen stays white. A blank frame is still
+    // reported every 70224 dots (total_cycles counts the dots into it) so frontends keep presenting
+    fn lcd_off_dot(&mut self, addr_space: &mut AddrSpace) -> bool {
+        if self.lcd_on {
+            self.lcd_on = false;
+            self.total_cycles = 0;
+            self.stat_line = false;
+            addr_space.set_ly(0);
+            self.set_gpu_mode(0, addr_space);
+            self.pixels = [0xFFFFFFFF; WIDTH * HEIGHT];
+            return true;
+        }
+
+        self.total_cycles += 1;
+        if self.total_cycles >= 456 * 154 {
+            self.total_cycles = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn update_stat_line(&mut self, addr_space: &mut AddrSpace) {
+        set_lyc_eq_ly(addr_space);
+
+        let stat = addr_space.read(LCD_STAT_ADDR);
+        let mode = stat & 0x3;
+        let coincidence = stat & (1 << 2) > 0;
+
+        // On DMG a CPU write to STAT behaves as if all the sources were enabled for one cycle
+        if addr_space.take_stat_write() && !self.stat_line && (mode < 2 || coincidence) {
+            addr_space.set_if_lc_stat(true);
+        }
+
+        let line = (stat & (1 << 6) > 0 && coincidence)
+            || (stat & (1 << 3) > 0 && mode == 0)
+            || (stat & (1 << 4) > 0 && mode == 1)
+            || (stat & (1 << 5) > 0 && mode == 2);
+        if line && !self.stat_line {
+            addr_space.set_if_lc_stat(true);
+        }
+        self.stat_line = line;
     }
 }
 
@@ -73,9 +311,9 @@ fn ly(addr_space: &AddrSpace) -> u8 {
 fn inc_ly(addr_space: &mut AddrSpace) {
     let ly = addr_space.read(LY_ADDR);
     if ly >= 153 {
-        addr_space.write(LY_ADDR, 0);
+        addr_space.set_ly(0);
     } else {
-        addr_space.write(LY_ADDR, ly + 1);
+        addr_space.set_ly(ly + 1);
     }
 }
 
@@ -83,31 +321,35 @@ fn set_lyc_eq_ly(addr_space: &mut AddrSpace) {
     let ly = addr_space.read(LY_ADDR);
     let lyc = addr_space.read(LYC_ADDR);
     let stat = addr_space.read(LCD_STAT_ADDR);
-    if (ly == lyc) && (stat & (1 << 2)) == 0 {
-        addr_space.write(LCD_STAT_ADDR, stat | (1 << 2));
-        if stat | (1 << 6) > 0 {
-            addr_space.set_if_lc_stat(true);
-        }
-    } else if (ly != lyc) && (stat & (1 << 2)) > 0 {
-        addr_space.write(LCD_STAT_ADDR, stat & !(1 << 2));
+    if ly == lyc {
+        addr_space.set_stat(stat | (1 << 2));
+    } else {
+        addr_space.set_stat(stat & !(1 << 2));
     }
 }
 
-fn palette(palette_byte: u8, transparency: bool) -> [u32; 4] {
+fn palette(palette_byte: u8) -> [u32; 4] {
     let c0 = palette_byte & 0x3;
     let c1 = (palette_byte & (0x3 << 2)) >> 2;
     let c2 = (palette_byte & (0x3 << 4)) >> 4;
     let c3 = (palette_byte & (0x3 << 6)) >> 6;
     [c0, c1, c2, c3].map(|c| match c {
-        1 => 0xFF555555,
-        2 => 0xFFAAAAAA,
+        1 => 0xFFAAAAAA,
+        2 => 0xFF555555,
         3 => 0xFF000000,
-        0 if transparency => 0,
         0 => 0xFFFFFFFF,
         _ => panic!("Invalid color!"),
     })
 }
 
+fn tile_map_addr(high_map: bool) -> u16 {
+    if high_map {
+        TILE_MAP_ADDR_2
+    } else {
+        TILE_MAP_ADDR
+    }
+}
+
 fn bg_tiles_addr(lcdc: u8, tile_index: u8, offset: u16) -> u16 {
     if lcdc & 0x10 > 0 {
         VRAM_ADDR_START + (tile_index as u16 * 16) + offset
@@ -116,106 +358,197 @@ fn bg_tiles_addr(lcdc: u8, tile_index: u8, offset: u16) -> u16 {
     }
 }
 
-fn scanline(addr_space: &AddrSpace, pixels: &mut [u32; 160 * 144]) {
+#[derive(Clone, Copy)]
+struct Sprite {
+    y: i16,
+    x: i16,
+    tile: u8,
+    flags: u8,
+}
+
+impl Sprite {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.y as u16);
+        state.write_u16(self.x as u16);
+        state.write_u8(self.tile);
+        state.write_u8(self.flags);
+    }
+
+    fn load_state(state: &mut StateReader) -> Result<Sprite, SaveStateError> {
+        Ok(Sprite {
+            y: state.read_u16()? as i16,
+            x: state.read_u16()? as i16,
+            tile: state.read_u8()?,
+            flags: state.read_u8()?,
+        })
+    }
+
+    fn palette_addr(&self) -> u16 {
+        OBJ0_PALETTE_ADDR + ((self.flags >> 4) & 1) as u16
+    }
+
+    fn bg_over_obj(&self) -> bool {
+        self.flags & (1 << 7) > 0
+    }
+
+    // Colour indices of the sprite row that falls on the given line, already flipped
+    fn line_colors(&self, lcdc: u8, h_line: u8, addr_space: &AddrSpace) -> [u8; 8] {
+        let obj_size = if lcdc & 0x4 > 0 { 16 } else { 8 };
+        let x_flip = self.flags & (1 << 5) > 0;
+        let y_flip = self.flags & (1 << 6) > 0;
+
+        let mut line = h_line as i16 - self.y;
+        if y_flip {
+            line = obj_size - line - 1;
+        }
+        // In 8x16 mode the tile index LSB is ignored
+        let tile = if obj_size == 16 {
+            self.tile & 0xFE
+        } else {
+            self.tile
+        } as u16;
+
+        let b1 = addr_space.read(VRAM_ADDR_START + tile * 16 + line as u16 * 2);
+        let b2 = addr_space.read(VRAM_ADDR_START + tile * 16 + line as u16 * 2 + 1);
+        let mut colors = tile_line_colors(b1, b2);
+        if x_flip {
+            colors.reverse();
+        }
+        colors
+    }
+}
+
+// Selects the first 10 sprites in OAM order that overlap the given line
+fn oam_scan(addr_space: &AddrSpace, h_line: u8) -> Vec<Sprite> {
+    let obj_size = if addr_space.read(LCDC_ADDR) & 0x4 > 0 {
+        16
+    } else {
+        8
+    };
+
+    let mut sprites = Vec::with_capacity(10);
+    for s in 0..40 {
+        let y = addr_space.read(OAM_ADDR + s * 4) as i16 - 16;
+        if (h_line as i16) >= y && (h_line as i16) < y + obj_size {
+            sprites.push(Sprite {
+                y,
+                x: addr_space.read(OAM_ADDR + s * 4 + 1) as i16 - 8,
+                tile: addr_space.read(OAM_ADDR + s * 4 + 2),
+                flags: addr_space.read(OAM_ADDR + s * 4 + 3),
+            });
+            if sprites.len() == 10 {
+                break;
+            }
+        }
+    }
+    // On DMG the sprite with the smaller X wins, ties go to the lower OAM index
+    sprites.sort_by_key(|s| s.x);
+    sprites
+}
+
+// Draws the current line, returns true if the window was drawn on it
+fn scanline(addr_space: &AddrSpace, pixels: &mut [u32; 160 * 144], window_line: u8) -> bool {
     let lcdc = addr_space.read(LCDC_ADDR);
     let scroll_y = addr_space.read(SCY_ADDR) as u16;
     let scroll_x = addr_space.read(SCX_ADDR) as u16;
     let h_line = addr_space.read(LY_ADDR) as u16;
-    let bg_palette = palette(addr_space.read(BG_PALETTE_ADDR), false);
-    let window_y = addr_space.read(WY_ADDR) as i16;
-    let window_x = addr_space.read(WX_ADDR) as i16;
+    let bg_palette = palette(addr_space.read(BG_PALETTE_ADDR));
+    let window_x = addr_space.read(WX_ADDR) as u16;
+    let bg_map = tile_map_addr(addr_space.bg_tile_map_area());
+    let window_map = tile_map_addr(addr_space.window_tile_map_area());
+
+    // On DMG clearing LCDC bit 0 blanks both the background and the window
+    let bg_enable = lcdc & 0x1 > 0;
+    let window_visible = window_visible(addr_space);
+
+    let mut bg_colors = [0_u8; WIDTH];
 
     let y = (h_line + scroll_y) % 256;
     for x in 0..160 {
-        let tile_index = (y / 8) * 32 + (((scroll_x + x) % 256) / 8);
-        let sprite_y = y % 8;
-        let sprite_x = ((scroll_x + x) % 256) % 8;
-
-        let tile = addr_space.read(TILE_MAP_ADDR + tile_index);
-        let b1 = addr_space.read(bg_tiles_addr(lcdc, tile, sprite_y * 2));
-        let b2 = addr_space.read(bg_tiles_addr(lcdc, tile, 1 + sprite_y * 2));
-        let pix = double_byte_to_pixels(b1, b2, &bg_palette);
-
-        pixels[(h_line * 160 + x) as usize] = pix[sprite_x as usize];
-
-        if lcdc & (1 << 5) > 0 {
-            let y = h_line as i16 - window_y;
-            if y >= 0 && y <= 144 {
-                let sprite_x = (x as i16) - window_x + 7;
-                if sprite_x >= 0 && sprite_x <= 160 {
-                    let tile_index = ((y / 8) * 32 + (sprite_x / 8)) as u16;
-                    let sprite_y = (y % 8) as u16;
-
-                    let tile = addr_space.read(TILE_MAP_ADDR_2 + tile_index);
-                    let b1 = addr_space.read(bg_tiles_addr(lcdc, tile, sprite_y * 2));
-                    let b2 = addr_space.read(bg_tiles_addr(lcdc, tile, 1 + sprite_y * 2));
-                    let pix = double_byte_to_pixels(b1, b2, &bg_palette);
-
-                    pixels[(h_line * 160 + x as u16) as usize] = pix[(sprite_x % 8) as usize];
-                }
-            }
+        if !bg_enable {
+            break;
         }
-    }
 
-    let obj_size = if lcdc & 0x4 > 0 { 16 } else { 8 };
+        // With WX < 7 the window starts at the left edge with its first columns cut off
+        if window_visible && x + 7 >= window_x {
+            let window_col = x + 7 - window_x;
+            let tile_index = (window_line as u16 / 8) * 32 + (window_col / 8);
+            let sprite_y = window_line as u16 % 8;
 
-    for s in 0..40 {
-        let sprite_y = addr_space.read(OAM_ADDR + s * 4) as i16 - 16;
-        let sprite_x = addr_space.read(OAM_ADDR + s * 4 + 1) as i16 - 8;
-        let sprite_tile = addr_space.read(OAM_ADDR + s * 4 + 2) as u16;
-        let sprite_flags = addr_space.read(OAM_ADDR + s * 4 + 3);
-        let x_flip = sprite_flags & (1 << 5) > 0;
-        let y_flip = sprite_flags & (1 << 6) > 0;
-        let bg_over_obj = sprite_flags & (1 << 7) > 0;
-        let palette_i = sprite_flags & (1 << 4) >> 4;
-        let sprite_palette = palette(addr_space.read(OBJ0_PALETTE_ADDR + palette_i as u16), true);
-
-        if y as i16 >= sprite_y
-            && (y as i16) < sprite_y + obj_size
-            && (sprite_x + 8) >= 0
-            && sprite_x - 8 < 160
-        {
-            let sprite_y_line = if y_flip {
-                obj_size - (y as i16 - sprite_y) - 1
-            } else {
-                y as i16 - sprite_y
-            } as u16;
-
-            let b1 = addr_space.read(VRAM_ADDR_START + (sprite_tile * 16 + sprite_y_line * 2));
-            let b2 = addr_space.read(VRAM_ADDR_START + (sprite_tile * 16 + 1 + sprite_y_line * 2));
-            let mut pix = double_byte_to_pixels(b1, b2, &sprite_palette);
-            if x_flip {
-                pix.reverse();
-            }
+            let tile = addr_space.read(window_map + tile_index);
+            let b1 = addr_space.read(bg_tiles_addr(lcdc, tile, sprite_y * 2));
+            let b2 = addr_space.read(bg_tiles_addr(lcdc, tile, 1 + sprite_y * 2));
 
-            let screen_pix = h_line as i16 * 160 + (sprite_x as i16);
-            let mut px = 0;
-            for p in pix {
-                let pixel_x = sprite_x + px as i16;
-                let pix_index = (screen_pix + px as i16) as usize;
+            bg_colors[x as usize] = tile_line_colors(b1, b2)[(window_col % 8) as usize];
+        } else {
+            let tile_index = (y / 8) * 32 + (((scroll_x + x) % 256) / 8);
+            let sprite_y = y % 8;
+            let sprite_x = ((scroll_x + x) % 256) % 8;
 
-                if pixel_x >= 0 && pixel_x < 160 {
-                    if p != 0 && (pixels[pix_index] == 0xFF000000 || !bg_over_obj) {
-                        pixels[pix_index] = p;
-                    }
-                }
-                px += 1;
+            let tile = addr_space.read(bg_map + tile_index);
+            let b1 = addr_space.read(bg_tiles_addr(lcdc, tile, sprite_y * 2));
+            let b2 = addr_space.read(bg_tiles_addr(lcdc, tile, 1 + sprite_y * 2));
+
+            bg_colors[x as usize] = tile_line_colors(b1, b2)[sprite_x as usize];
+        }
+    }
+
+    // Sprites are in priority order, so a pixel is only taken by the first opaque sprite on it,
+    // even if that sprite ends up hidden behind the background
+    let mut obj_pixels: [Option<(u8, Sprite)>; WIDTH] = [None; WIDTH];
+    let sprites = if lcdc & 0x2 > 0 {
+        oam_scan(addr_space, h_line as u8)
+    } else {
+        Vec::new()
+    };
+    for sprite in sprites {
+        let colors = sprite.line_colors(lcdc, h_line as u8, addr_space);
+        for (px, &color) in colors.iter().enumerate() {
+            let pixel_x = sprite.x + px as i16;
+            if color != 0 && (0..WIDTH as i16).contains(&pixel_x) {
+                obj_pixels[pixel_x as usize].get_or_insert((color, sprite));
             }
         }
     }
+
+    let line = &mut pixels[h_line as usize * WIDTH..(h_line as usize + 1) * WIDTH];
+    for x in 0..WIDTH {
+        let bg = bg_colors[x];
+        line[x] = match obj_pixels[x] {
+            Some((color, sprite)) if !(sprite.bg_over_obj() && bg != 0) => {
+                palette(addr_space.read(sprite.palette_addr()))[color as usize]
+            }
+            _ => bg_palette[bg as usize],
+        };
+    }
+
+    window_visible
 }
 
-fn double_byte_to_pixels(b1: u8, b2: u8, palette: &[u32; 4]) -> [u32; 8] {
-    let mut pixels = [0; 8];
+// True if the window shows up on the current line
+fn window_visible(addr_space: &AddrSpace) -> bool {
+    let bg_enable = addr_space.read(LCDC_ADDR) & 0x1 > 0;
+    let h_line = addr_space.read(LY_ADDR);
+    let window_y = addr_space.read(WY_ADDR);
+    let window_x = addr_space.read(WX_ADDR);
+    bg_enable && addr_space.window_enable() && h_line >= window_y && window_x <= 166
+}
+
+// Colour indices of the 8 pixels of a tile line, leftmost first
+fn tile_line_colors(b1: u8, b2: u8) -> [u8; 8] {
+    let mut colors = [0; 8];
 
     for bit in 0..8 {
         let i = bit;
         let bitmask = 2_u8.pow(i);
-        let color_code = (((b1 & bitmask) >> i) << 1) | (b2 & (bitmask)) >> i;
-
-        pixels[(7 - i) as usize] = palette[color_code as usize];
+        // The first byte holds the low bit of each colour index, the second one the high bit
+        colors[(7 - i) as usize] = (((b2 & bitmask) >> i) << 1) | (b1 & bitmask) >> i;
     }
-    pixels
+    colors
+}
+
+fn double_byte_to_pixels(b1: u8, b2: u8, palette: &[u32; 4]) -> [u32; 8] {
+    tile_line_colors(b1, b2).map(|color_code| palette[color_code as usize])
 }
 
 pub fn video_ram_as_pixels(addr_space: &AddrSpace, pixels: &mut [u32; 256 * 256]) {
@@ -223,7 +556,7 @@ pub fn video_ram_as_pixels(addr_space: &AddrSpace, pixels: &mut [u32; 256 * 256]
         let sprite_n = (i * 2) / 16;
         let sprite_x = (sprite_n % 32) * 8;
         let sprite_y = ((i * 2) % 16) / 2 + (8 * (sprite_n / 32));
-        let palette = palette(addr_space.read(BG_PALETTE_ADDR), false);
+        let palette = palette(addr_space.read(BG_PALETTE_ADDR));
 
         let sprite_line = double_byte_to_pixels(
             addr_space.read(VRAM_ADDR_START + (i * 2)),
@@ -236,7 +569,7 @@ pub fn video_ram_as_pixels(addr_space: &AddrSpace, pixels: &mut [u32; 256 * 256]
 }
 
 pub fn full_frame_buffer(addr_space: &AddrSpace, pixels: &mut [u32; 256 * 256]) {
-    let palette = palette(addr_space.read(BG_PALETTE_ADDR), false);
+    let palette = palette(addr_space.read(BG_PALETTE_ADDR));
 
     for y in 0..256 {
         for x in 0..(256 / 8) {
@@ -0,0 +1,318 @@
+use std::collections::VecDeque;
+
+use super::*;
+
+#[derive(Clone, Copy)]
+struct ObjPixel {
+    color: u8,
+    palette_addr: u16,
+    bg_over_obj: bool,
+}
+
+const TRANSPARENT: ObjPixel = ObjPixel {
+    color: 0,
+    palette_addr: OBJ0_PALETTE_ADDR,
+    bg_over_obj: false,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+enum FetchStep {
+    Tile,
+    DataLow,
+    DataHigh,
+    Push,
+}
+
+// Background/window fetcher and pixel FIFOs used during mode 3.
+// Registers are read at the dot the hardware would read them, so mid-line writes take effect
+pub(super) struct Fifo {
+    bg: VecDeque<u8>,
+    obj: VecDeque<ObjPixel>,
+    step: FetchStep,
+    step_dots: u8,
+    fetch_x: u8,
+    tile: u8,
+    data_low: u8,
+    data_high: u8,
+    lx: u8,
+    discard: u8,
+    stall: u8,
+    window: bool,
+    window_line: u8,
+    sprites: Vec<Sprite>,
+    sprite_dots: u8,
+}
+
+impl Fifo {
+    pub(super) fn new() -> Self {
+        Self {
+            bg: VecDeque::with_capacity(16),
+            obj: VecDeque::with_capacity(8),
+            step: FetchStep::Tile,
+            step_dots: 0,
+            fetch_x: 0,
+            tile: 0,
+            data_low: 0,
+            data_high: 0,
+            lx: 0,
+            discard: 0,
+            stall: 0,
+            window: false,
+            window_line: 0,
+            sprites: Vec::with_capacity(10),
+            sprite_dots: 0,
+        }
+    }
+
+    // Called when mode 3 begins
+    pub(super) fn start(&mut self, addr_space: &AddrSpace, window_line: u8) {
+        self.bg.clear();
+        self.obj.clear();
+        self.restart_fetcher();
+        self.lx = 0;
+        self.discard = addr_space.read(SCX_ADDR) & 0x7;
+        // The first tile fetch of every line is thrown away
+        self.stall = 6;
+        self.window = false;
+        self.window_line = window_line;
+        self.sprites = oam_scan(addr_space, ly(addr_space));
+        self.sprite_dots = 0;
+    }
+
+    pub(super) fn save_state(&self, state: &mut StateWriter) {
+        state.write_len(self.bg.len());
+        for &color in &self.bg {
+            state.write_u8(color);
+        }
+        state.write_len(self.obj.len());
+        for pixel in &self.obj {
+            state.write_u8(pixel.color);
+            state.write_u16(pixel.palette_addr);
+            state.write_bool(pixel.bg_over_obj);
+        }
+        state.write_u8(self.step as u8);
+        for value in [
+            self.step_dots,
+            self.fetch_x,
+            self.tile,
+            self.data_low,
+            self.data_high,
+            self.lx,
+            self.discard,
+            self.stall,
+        ] {
+            state.write_u8(value);
+        }
+        state.write_bool(self.window);
+        state.write_u8(self.window_line);
+        state.write_len(self.sprites.len());
+        for sprite in &self.sprites {
+            sprite.save_state(state);
+        }
+        state.write_u8(self.sprite_dots);
+    }
+
+    pub(super) fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.bg.clear();
+        for _ in 0..state.read_len()? {
+            self.bg.push_back(state.read_u8()?);
+        }
+        self.obj.clear();
+        for _ in 0..state.read_len()? {
+            self.obj.push_back(ObjPixel {
+                color: state.read_u8()?,
+                palette_addr: state.read_u16()?,
+                bg_over_obj: state.read_bool()?,
+            });
+        }
+        self.step = match state.read_u8()? {
+            0 => FetchStep::Tile,
+            1 => FetchStep::DataLow,
+            2 => FetchStep::DataHigh,
+            3 => FetchStep::Push,
+            _ => return Err(SaveStateError::Corrupted),
+        };
+        for value in [
+            &mut self.step_dots,
+            &mut self.fetch_x,
+            &mut self.tile,
+            &mut self.data_low,
+            &mut self.data_high,
+            &mut self.lx,
+            &mut self.discard,
+            &mut self.stall,
+        ] {
+            *value = state.read_u8()?;
+        }
+        self.window = state.read_bool()?;
+        self.window_line = state.read_u8()?;
+        self.sprites.clear();
+        for _ in 0..state.read_len()? {
+            self.sprites.push(Sprite::load_state(state)?);
+        }
+        self.sprite_dots = state.read_u8()?;
+        Ok(())
+    }
+
+    pub(super) fn drew_window(&self) -> bool {
+        self.window
+    }
+
+    fn restart_fetcher(&mut self) {
+        self.step = FetchStep::Tile;
+        self.step_dots = 0;
+        self.fetch_x = 0;
+    }
+
+    // Runs one dot of mode 3, returns true once the 160 pixels of the line have been pushed
+    pub(super) fn dot(
+        &mut self,
+        addr_space: &AddrSpace,
+        pixels: &mut [u32; WIDTH * HEIGHT],
+    ) -> bool {
+        // The line was finished before, as with a state saved while the Scanline renderer drew
+        if self.lx as usize >= WIDTH {
+            return true;
+        }
+        if self.stall > 0 {
+            self.stall -= 1;
+            return false;
+        }
+
+        let lcdc = addr_space.read(LCDC_ADDR);
+        let h_line = ly(addr_space);
+
+        if self.sprite_dots > 0 {
+            self.sprite_dots -= 1;
+            if self.sprite_dots == 0 {
+                let sprite = self.sprites.remove(0);
+                self.push_sprite(sprite, lcdc, h_line, addr_space);
+            }
+            return false;
+        }
+
+        if self.discard == 0 && lcdc & 0x2 > 0 {
+            if let Some(sprite) = self.sprites.first() {
+                if sprite.x <= self.lx as i16 {
+                    // The sprite fetch waits for the background fetcher to fill the FIFO first
+                    if self.bg.is_empty() {
+                        self.fetch(lcdc, h_line, addr_space);
+                    } else {
+                        self.sprite_dots = 6;
+                    }
+                    return false;
+                }
+            }
+        }
+
+        if self.discard == 0 && !self.window && lcdc & 0x21 == 0x21 {
+            let window_y = addr_space.read(WY_ADDR);
+            let window_x = addr_space.read(WX_ADDR);
+            if h_line >= window_y && self.lx as u16 + 7 >= window_x as u16 {
+                self.window = true;
+                self.bg.clear();
+                self.restart_fetcher();
+                // With WX < 7 the first columns of the window are cut off
+                self.discard = 7_u8.saturating_sub(window_x);
+                return false;
+            }
+        }
+
+        self.fetch(lcdc, h_line, addr_space);
+
+        let bg = match self.bg.pop_front() {
+            Some(bg) => bg,
+            None => return false,
+        };
+        if self.discard > 0 {
+            self.discard -= 1;
+            return false;
+        }
+        let obj = self.obj.pop_front().unwrap_or(TRANSPARENT);
+        // On DMG clearing LCDC bit 0 blanks both the background and the window
+        let bg = if lcdc & 0x1 > 0 { bg } else { 0 };
+
+        let color = if obj.color != 0 && !(obj.bg_over_obj && bg != 0) {
+            palette(addr_space.read(obj.palette_addr))[obj.color as usize]
+        } else {
+            palette(addr_space.read(BG_PALETTE_ADDR))[bg as usize]
+        };
+        pixels[h_line as usize * WIDTH + self.lx as usize] = color;
+        self.lx += 1;
+
+        self.lx as usize == WIDTH
+    }
+
+    fn fetch(&mut self, lcdc: u8, h_line: u8, addr_space: &AddrSpace) {
+        self.step_dots += 1;
+        match self.step {
+            FetchStep::Tile if self.step_dots >= 2 => {
+                let tile_index = if self.window {
+                    (self.window_line as u16 / 8) * 32 + self.fetch_x as u16
+                } else {
+                    let y = (h_line as u16 + addr_space.read(SCY_ADDR) as u16) % 256;
+                    let x = (addr_space.read(SCX_ADDR) as u16 / 8 + self.fetch_x as u16) % 32;
+                    (y / 8) * 32 + x
+                };
+                let map = if self.window {
+                    tile_map_addr(lcdc & 0x40 > 0)
+                } else {
+                    tile_map_addr(lcdc & 0x08 > 0)
+                };
+                self.tile = addr_space.read(map + tile_index);
+                self.step = FetchStep::DataLow;
+                self.step_dots = 0;
+            }
+            FetchStep::DataLow if self.step_dots >= 2 => {
+                let addr = self.tile_data_addr(lcdc, h_line, 0, addr_space);
+                self.data_low = addr_space.read(addr);
+                self.step = FetchStep::DataHigh;
+                self.step_dots = 0;
+            }
+            FetchStep::DataHigh if self.step_dots >= 2 => {
+                let addr = self.tile_data_addr(lcdc, h_line, 1, addr_space);
+                self.data_high = addr_space.read(addr);
+                self.step = FetchStep::Push;
+                self.step_dots = 0;
+            }
+            FetchStep::Push if self.bg.is_empty() => {
+                self.bg
+                    .extend(tile_line_colors(self.data_low, self.data_high));
+                self.fetch_x = self.fetch_x.wrapping_add(1);
+                self.step = FetchStep::Tile;
+                self.step_dots = 0;
+            }
+            _ => {}
+        }
+    }
+
+    fn tile_data_addr(&self, lcdc: u8, h_line: u8, offset: u16, addr_space: &AddrSpace) -> u16 {
+        let y = if self.window {
+            self.window_line as u16
+        } else {
+            (h_line as u16 + addr_space.read(SCY_ADDR) as u16) % 256
+        };
+        bg_tiles_addr(lcdc, self.tile, (y % 8) * 2 + offset)
+    }
+
+    fn push_sprite(&mut self, sprite: Sprite, lcdc: u8, h_line: u8, addr_space: &AddrSpace) {
+        let colors = sprite.line_colors(lcdc, h_line, addr_space);
+
+        while self.obj.len() < 8 {
+            self.obj.push_back(TRANSPARENT);
+        }
+        // Sprites hanging off the left edge only push their visible part
+        let skip = (self.lx as i16 - sprite.x) as usize;
+        for (i, &color) in colors.iter().enumerate().skip(skip) {
+            let slot = &mut self.obj[i - skip];
+            // Pixels already in the FIFO belong to sprites with higher priority
+            if slot.color == 0 {
+                *slot = ObjPixel {
+                    color,
+                    palette_addr: sprite.palette_addr(),
+                    bg_over_obj: sprite.bg_over_obj(),
+                };
+            }
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use super::*;
+
+// Runs the PPU until the next mode 3 starts and returns how many dots it lasts
+fn mode_3_length(ppu: &mut PPU, addr_space: &mut AddrSpace) -> u32 {
+    while ppu.gpu_mode(addr_space) != 3 {
+        ppu.tick(1, addr_space);
+    }
+    let mut dots = 0;
+    while ppu.gpu_mode(addr_space) == 3 {
+        ppu.tick(1, addr_space);
+        dots += 1;
+    }
+    dots
+}
+
+fn fifo_setup() -> (PPU, AddrSpace) {
+    let mut addr_space = AddrSpace::empty();
+    addr_space.write(LCDC_ADDR, 0x91);
+    addr_space.set_stat(0x80);
+    addr_space.set_ly(0);
+    addr_space.write(SCX_ADDR, 0);
+    let mut ppu = PPU::new();
+    ppu.set_renderer(Renderer::Fifo);
+    (ppu, addr_space)
+}
+
+#[test]
+fn test_fifo_mode_3_length() {
+    let (mut ppu, mut addr_space) = fifo_setup();
+    assert_eq!(mode_3_length(&mut ppu, &mut addr_space), 172);
+
+    addr_space.write(SCX_ADDR, 3);
+    assert_eq!(mode_3_length(&mut ppu, &mut addr_space), 175);
+}
+
+#[test]
+fn test_switch_renderer_in_mode_3() {
+    let (mut ppu, mut addr_space) = fifo_setup();
+    ppu.tick(456 * 154, &mut addr_space);
+    ppu.set_renderer(Renderer::Scanline);
+    assert_eq!(mode_3_length(&mut ppu, &mut addr_space), 172);
+
+    // Switching back halfway through a line leaves it to the Scanline renderer
+    while ppu.gpu_mode(&addr_space) != 3 {
+        ppu.tick(1, &mut addr_space);
+    }
+    ppu.tick(10, &mut addr_space);
+    ppu.set_renderer(Renderer::Fifo);
+    addr_space.write(SCX_ADDR, 3);
+    assert_eq!(mode_3_length(&mut ppu, &mut addr_space), 172 - 10);
+    assert_eq!(mode_3_length(&mut ppu, &mut addr_space), 175);
+
+    // A state saved by the Scanline renderer can leave the Fifo one with a finished line
+    ppu.set_renderer(Renderer::Scanline);
+    mode_3_length(&mut ppu, &mut addr_space);
+    while ppu.gpu_mode(&addr_space) != 3 {
+        ppu.tick(1, &mut addr_space);
+    }
+    ppu.renderer = Renderer::Fifo;
+    ppu.next_renderer = Renderer::Fifo;
+    ppu.tick(1, &mut addr_space);
+    assert_eq!(ppu.gpu_mode(&addr_space), 0);
+    ppu.tick(456 * 154, &mut addr_space);
+}
+
+#[test]
+fn test_fifo_mid_line_palette_change() {
+    let (mut ppu, mut addr_space) = fifo_setup();
+    addr_space.write(BG_PALETTE_ADDR, 0x00);
+
+    while ppu.gpu_mode(&addr_space) != 3 {
+        ppu.tick(1, &mut addr_space);
+    }
+    ppu.tick(6 + 80, &mut addr_space);
+    addr_space.write(BG_PALETTE_ADDR, 0xFF);
+    ppu.tick(200, &mut addr_space);
+
+    let line = &ppu.pixels[0..WIDTH];
+    assert_eq!(line[0], 0xFFFFFFFF);
+    assert_eq!(line[159], 0xFF000000);
+}
+
+#[test]
+fn test_sprite_limit_and_priority() {
+    let mut addr_space = AddrSpace::empty();
+    addr_space.write(LCDC_ADDR, 0x93);
+    addr_space.set_ly(0);
+    addr_space.write(SCX_ADDR, 0);
+    addr_space.write(SCY_ADDR, 0);
+    addr_space.write(BG_PALETTE_ADDR, 0xE4);
+    addr_space.write(OBJ0_PALETTE_ADDR, 0xE4);
+    addr_space.write(OBJ1_PALETTE_ADDR, 0x00);
+    for i in 0..16 {
+        addr_space.write(VRAM_ADDR_START + 16 + i, 0xFF);
+    }
+
+    // 11 sprites on the same line, only the first 10 in OAM order are drawn
+    for s in 0..40_u16 {
+        let on_line = s < 11;
+        addr_space.write(OAM_ADDR + s * 4, if on_line { 16 } else { 0 });
+        addr_space.write(OAM_ADDR + s * 4 + 1, (8 + 8 * s) as u8);
+        addr_space.write(OAM_ADDR + s * 4 + 2, 1);
+        addr_space.write(OAM_ADDR + s * 4 + 3, 0);
+    }
+    // Sprite 1 uses OBP1 and sprite 2 is behind the background, which is colour 0 here
+    addr_space.write(OAM_ADDR + 4 + 3, 1 << 4);
+    addr_space.write(OAM_ADDR + 8 + 3, 1 << 7);
+
+    let mut pixels = [0; WIDTH * HEIGHT];
+    scanline(&addr_space, &mut pixels, 0);
+
+    assert_eq!(pixels[0], 0xFF000000);
+    assert_eq!(pixels[8], 0xFFFFFFFF);
+    assert_eq!(pixels[16], 0xFF000000);
+    assert_eq!(pixels[79], 0xFF000000);
+    assert_eq!(pixels[80], 0xFFFFFFFF);
+}
+
+#[test]
+fn test_overlapping_sprite_priority() {
+    let mut addr_space = AddrSpace::empty();
+    addr_space.write(LCDC_ADDR, 0x93);
+    addr_space.set_ly(0);
+    addr_space.write(SCX_ADDR, 0);
+    addr_space.write(SCY_ADDR, 0);
+    addr_space.write(BG_PALETTE_ADDR, 0xE4);
+    addr_space.write(OBJ0_PALETTE_ADDR, 0xE4);
+    addr_space.write(OBJ1_PALETTE_ADDR, 0x40);
+    for i in 0..16 {
+        addr_space.write(VRAM_ADDR_START + 16 + i, 0xFF);
+    }
+
+    // (screen X, OBP1) in OAM order. OBP0 draws black and OBP1 grey
+    let sprites = [
+        (20, true),
+        (16, false),
+        (40, false),
+        (40, true),
+        (60, true),
+        (60, false),
+    ];
+    for s in 0..40_u16 {
+        let (x, obp1) = sprites.get(s as usize).copied().unwrap_or((0, false));
+        let on_line = (s as usize) < sprites.len();
+        addr_space.write(OAM_ADDR + s * 4, if on_line { 16 } else { 0 });
+        addr_space.write(OAM_ADDR + s * 4 + 1, x + 8);
+        addr_space.write(OAM_ADDR + s * 4 + 2, 1);
+        addr_space.write(OAM_ADDR + s * 4 + 3, if obp1 { 1 << 4 } else { 0 });
+    }
+
+    let mut pixels = [0; WIDTH * HEIGHT];
+    scanline(&addr_space, &mut pixels, 0);
+
+    // The smaller X wins even though the sprite comes later in OAM
+    assert_eq!(pixels[20], 0xFF000000);
+    assert_eq!(pixels[23], 0xFF000000);
+    assert_eq!(pixels[24], 0xFFAAAAAA);
+    // Same X, the lower OAM index wins
+    assert_eq!(pixels[40], 0xFF000000);
+    assert_eq!(pixels[60], 0xFFAAAAAA);
+}
+
+#[test]
+fn test_window_line_counter() {
+    for renderer in [Renderer::Scanline, Renderer::Fifo] {
+        let mut addr_space = AddrSpace::empty();
+        addr_space.write(LCDC_ADDR, 0xB1);
+        addr_space.set_stat(0x80);
+        addr_space.set_ly(0);
+        addr_space.write(WY_ADDR, 0);
+        addr_space.write(WX_ADDR, 200);
+        let mut ppu = PPU::new();
+        ppu.set_renderer(renderer);
+
+        // The window is off screen, so its line counter must not move
+        ppu.tick(456 * 4, &mut addr_space);
+        assert_eq!(ppu.window_line, 0);
+
+        addr_space.write(WX_ADDR, 166);
+        ppu.tick(456 * 2, &mut addr_space);
+        assert_eq!(ppu.window_line, 2);
+    }
+}
+
+#[test]
+fn test_stat_interrupt_blocking() {
+    let mut addr_space = AddrSpace::empty();
+    addr_space.write(LCDC_ADDR, 0x91);
+    addr_space.set_stat(0x80);
+    addr_space.set_ly(0);
+    addr_space.write(LYC_ADDR, 1);
+    addr_space.write(IF_ADDR, 0);
+    addr_space.take_stat_write();
+    let mut ppu = PPU::new();
+
+    // Mode 0 and LYC sources are both enabled, line 0 ends in mode 0 and line 1 matches LYC,
+    // so the line never goes low between them and only one interrupt is requested
+    addr_space.write(LCD_STAT_ADDR, 0x48);
+    addr_space.take_stat_write();
+    ppu.tick(455, &mut addr_space);
+    assert!(addr_space.if_lc_stat());
+    addr_space.set_if_lc_stat(false);
+    ppu.tick(2, &mut addr_space);
+    assert!(!addr_space.if_lc_stat());
+
+    // Writing STAT while the line is low triggers the DMG quirk during mode 0
+    addr_space.write(LYC_ADDR, 100);
+    addr_space.write(LCD_STAT_ADDR, 0x00);
+    ppu.tick(456, &mut addr_space);
+    addr_space.set_if_lc_stat(false);
+    while ppu.gpu_mode(&addr_space) != 0 {
+        ppu.tick(1, &mut addr_space);
+    }
+    addr_space.write(LCD_STAT_ADDR, 0x00);
+    ppu.tick(1, &mut addr_space);
+    assert!(addr_space.if_lc_stat());
+}
+
+#[test]
+fn test_lcd_off() {
+    let (mut ppu, mut addr_space) = fifo_setup();
+    ppu.tick(456 * 10 + 100, &mut addr_space);
+    assert_eq!(addr_space.read(LY_ADDR), 10);
+
+    addr_space.write(LCDC_ADDR, 0x11);
+    assert!(ppu.tick(1, &mut addr_space));
+    assert_eq!(addr_space.read(LY_ADDR), 0);
+    assert_eq!(ppu.gpu_mode(&addr_space), 0);
+    assert!(ppu.pixels.iter().all(|&p| p == 0xFFFFFFFF));
+
+    // LY stays at 0 and blank frames keep being reported
+    assert!(!ppu.tick(456 * 154 - 1, &mut addr_space));
+    assert!(ppu.tick(1, &mut addr_space));
+    assert_eq!(addr_space.read(LY_ADDR), 0);
+
+    // Turning it back on skips the OAM scan of the first line
+    addr_space.write(LCDC_ADDR, 0x91);
+    ppu.tick(76, &mut addr_space);
+    assert_eq!(ppu.gpu_mode(&addr_space), 3);
+    assert_eq!(addr_space.read(LY_ADDR), 0);
+}
+
+#[test]
+fn test_skip_rendering() {
+    // Window on from line 0, background and window tiles all of colour 3
+    let mut addr_space = AddrSpace::empty();
+    addr_space.write(LCDC_ADDR, 0xB1);
+    addr_space.set_stat(0x80);
+    addr_space.set_ly(0);
+    addr_space.write(WY_ADDR, 0);
+    addr_space.write(WX_ADDR, 7);
+    addr_space.write(BG_PALETTE_ADDR, 0xE4);
+    for addr in 0x8000..0x8010 {
+        addr_space.write(addr, 0xFF);
+    }
+    let mut ppu = PPU::new();
+    ppu.skip_rendering = true;
+    ppu.tick(456 * 10, &mut addr_space);
+    assert!(ppu.pixels.iter().all(|&pixel| pixel == 0));
+    assert_eq!(ppu.window_line, 10);
+
+    ppu.skip_rendering = false;
+    ppu.tick(456, &mut addr_space);
+    assert_eq!(ppu.pixels[10 * WIDTH], 0xFF000000);
+    assert_eq!(ppu.window_line, 11);
+}
@@ -0,0 +1,180 @@
+use std::collections::VecDeque;
+#[cfg(test)]
+mod tests;
+
+// Snapshots per keyframe, including the keyframe
+const GROUP_SIZE: usize = 32;
+
+// Save states taken every few frames so play can be rewound. Snapshots are grouped behind a
+// keyframe holding a full state, the others only keep what differs from it: the state XORed
+// with the keyframe, with its runs of zeros packed. The oldest groups are dropped once the
+// snapshots take more than the memory budget
+pub struct Rewind {
+    // Frames between snapshots
+    interval: u32,
+    budget: usize,
+    frames_since_snapshot: u32,
+    groups: VecDeque<Group>,
+    // Bytes used by all the snapshots
+    size: usize,
+}
+
+struct Group {
+    keyframe: Vec<u8>,
+    deltas: Vec<Vec<u8>>,
+}
+
+impl Group {
+    fn size(&self) -> usize {
+        self.keyframe.len() + self.deltas.iter().map(Vec::len).sum::<usize>()
+    }
+}
+
+impl Rewind {
+    // The newest group is always kept, even if it alone goes over the budget
+    pub fn new(interval: u32, budget: usize) -> Self {
+        Rewind {
+            interval: interval.max(1),
+            budget,
+            frames_since_snapshot: 0,
+            groups: VecDeque::new(),
+            size: 0,
+        }
+    }
+
+    // Counts a finished frame, returns true when a snapshot has to be pushed for it
+    pub fn frame_finished(&mut self) -> bool {
+        self.frames_since_snapshot += 1;
+        self.frames_since_snapshot >= self.interval
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        self.frames_since_snapshot = 0;
+        match self.groups.back_mut() {
+            Some(group) if group.deltas.len() + 1 < GROUP_SIZE => {
+                let delta = compress(&group.keyframe, &state);
+                self.size += delta.len();
+                group.deltas.push(delta);
+            }
+            _ => {
+                self.size += state.len();
+                self.groups.push_back(Group {
+                    keyframe: state,
+                    deltas: Vec::new(),
+                });
+            }
+        }
+        while self.size > self.budget && self.groups.len() > 1 {
+            let group = self.groups.pop_front().unwrap();
+            self.size -= group.size();
+        }
+    }
+
+    // The state to go back to from `current`: the newest snapshot, or the one before it if the
+    // machine hasn't moved since it was taken. The snapshot returned stays in the buffer, so
+    // the oldest one is returned again once the history is exhausted
+    pub fn previous(&mut self, current: &[u8]) -> Option<Vec<u8>> {
+        self.frames_since_snapshot = 0;
+        let newest = self.newest()?;
+        if newest != current || self.snapshots() == 1 {
+            return Some(newest);
+        }
+        self.pop();
+        self.newest()
+    }
+
+    fn newest(&self) -> Option<Vec<u8>> {
+        let group = self.groups.back()?;
+        Some(match group.deltas.last() {
+            Some(delta) => decompress(&group.keyframe, delta),
+            None => group.keyframe.clone(),
+        })
+    }
+
+    fn pop(&mut self) {
+        if let Some(group) = self.groups.back_mut() {
+            match group.deltas.pop() {
+                Some(delta) => self.size -= delta.len(),
+                None => {
+                    self.size -= group.keyframe.len();
+                    self.groups.pop_back();
+                }
+            }
+        }
+    }
+
+    pub fn snapshots(&self) -> usize {
+        self.groups.iter().map(|group| group.deltas.len() + 1).sum()
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn clear(&mut self) {
+        self.groups.clear();
+        self.size = 0;
+        self.frames_since_snapshot = 0;
+    }
+}
+
+// The delta is a list of (zero run length, literal length, literal bytes) with both lengths as
+// LEB128 varints. Most of the state doesn't change between frames, so most of it is zeros
+fn compress(keyframe: &[u8], state: &[u8]) -> Vec<u8> {
+    let xored: Vec<u8> = state
+        .iter()
+        .enumerate()
+        .map(|(offset, &byte)| byte ^ keyframe.get(offset).copied().unwrap_or(0))
+        .collect();
+    let mut delta = Vec::new();
+    let mut i = 0;
+    while i < xored.len() {
+        let zeros = xored[i..].iter().take_while(|&&byte| byte == 0).count();
+        i += zeros;
+        let literals = xored[i..].iter().take_while(|&&byte| byte != 0).count();
+        write_varint(&mut delta, zeros);
+        write_varint(&mut delta, literals);
+        delta.extend_from_slice(&xored[i..i + literals]);
+        i += literals;
+    }
+    delta
+}
+
+fn decompress(keyframe: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut xored = Vec::with_capacity(keyframe.len());
+    let mut i = 0;
+    while i < delta.len() {
+        let zeros = read_varint(delta, &mut i);
+        let literals = read_varint(delta, &mut i);
+        xored.resize(xored.len() + zeros, 0);
+        xored.extend_from_slice(&delta[i..i + literals]);
+        i += literals;
+    }
+    xored
+        .iter()
+        .enumerate()
+        .map(|(offset, &byte)| byte ^ keyframe.get(offset).copied().unwrap_or(0))
+        .collect()
+}
+
+fn write_varint(data: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        data.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+fn read_varint(data: &[u8], i: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*i];
+        *i += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
@@ -0,0 +1,42 @@
+use super::*;
+
+#[test]
+fn test_delta_round_trip() {
+    let keyframe: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+    let mut state = keyframe.clone();
+    state[3] = 0xAA;
+    state[500..700].fill(0x55);
+    state[999] = 0;
+
+    let delta = compress(&keyframe, &state);
+    assert!(delta.len() < 220);
+    assert_eq!(decompress(&keyframe, &delta), state);
+    assert_eq!(
+        decompress(&keyframe, &compress(&keyframe, &keyframe)),
+        keyframe
+    );
+}
+
+#[test]
+fn test_rewind_order_and_budget() {
+    let state = |n: u8| {
+        let mut state = vec![0; 100];
+        state[0] = n;
+        state
+    };
+    let mut rewind = Rewind::new(2, 200);
+    for n in 0..70 {
+        if rewind.frame_finished() {
+            rewind.push(state(n));
+        }
+    }
+    // 35 snapshots of the odd frames, the first group of 32 was dropped to stay within budget
+    assert_eq!(rewind.snapshots(), 3);
+    assert!(rewind.size() <= 200);
+
+    assert_eq!(rewind.previous(&state(70)), Some(state(69)));
+    assert_eq!(rewind.previous(&state(69)), Some(state(67)));
+    assert_eq!(rewind.previous(&state(67)), Some(state(65)));
+    assert_eq!(rewind.previous(&state(65)), Some(state(65)));
+    assert_eq!(rewind.snapshots(), 1);
+}
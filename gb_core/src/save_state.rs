@@ -0,0 +1,249 @@
+use super::hash;
+use super::model::Model;
+use super::serial::Serial;
+use std::fmt;
+
+// Save state layout: a header with MAGIC, the format version, the model and a hash of the
+// cartridge ROM, followed by the state of every component in a fixed order (CPU, PPU, address
+// space and the devices plugged into it, joypad). Numbers are little endian
+const MAGIC: &[u8; 4] = b"GBSS";
+const HEADER_SIZE: usize = 4 + 2 + 1 + 8;
+
+// Bumped on every change to the layout
+pub const VERSION: u16 = 2;
+
+type Migration = fn(Vec<u8>) -> Result<Vec<u8>, SaveStateError>;
+
+// MIGRATIONS[i] turns the body of a version i + 1 state into version i + 2, so states saved by
+// older builds keep loading. Bumping VERSION without adding one doesn't compile
+const MIGRATIONS: [Migration; VERSION as usize - 1] = [add_serial];
+
+// Version 2 added the serial port after the timer, the last part of the address space. Only
+// the joypad and frame_ready come after it. Version 1 had no transfer in progress to carry over
+fn add_serial(mut body: Vec<u8>) -> Result<Vec<u8>, SaveStateError> {
+    let at = body.len().checked_sub(2).ok_or(SaveStateError::Truncated)?;
+    let mut serial = StateWriter::new();
+    Serial::new().save_state(&mut serial);
+    body.splice(at..at, serial.into_bytes());
+    Ok(body)
+}
+
+const MODELS: [Model; 7] = [
+    Model::DMG0,
+    Model::DMG,
+    Model::MGB,
+    Model::SGB,
+    Model::SGB2,
+    Model::CGB,
+    Model::AGB,
+];
+
+#[derive(Debug, PartialEq)]
+pub enum SaveStateError {
+    NotASaveState,
+    // Saved by a newer build
+    UnsupportedVersion(u16),
+    WrongModel { saved: Model, running: Model },
+    WrongRom,
+    Truncated,
+    Corrupted,
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveStateError::NotASaveState => write!(f, "Not a save state"),
+            SaveStateError::UnsupportedVersion(version) => write!(
+                f,
+                "Save state version {} is newer than the supported version {}",
+                version, VERSION
+            ),
+            SaveStateError::WrongModel { saved, running } => write!(
+                f,
+                "Save state is for a {:?}, the running model is a {:?}",
+                saved, running
+            ),
+            SaveStateError::WrongRom => write!(f, "Save state is for a different ROM"),
+            SaveStateError::Truncated => write!(f, "Save state is truncated"),
+            SaveStateError::Corrupted => write!(f, "Save state is corrupted"),
+        }
+    }
+}
+
+// Identifies the cartridge a state was saved with
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    hash::fnv1a(rom)
+}
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        StateWriter::new()
+    }
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter { data: Vec::new() }
+    }
+
+    // Starts a save state with its header
+    pub fn with_header(model: Model, rom_hash: u64) -> Self {
+        let mut writer = StateWriter::new();
+        writer.write_bytes(MAGIC);
+        writer.write_u16(VERSION);
+        writer.write_model(model);
+        writer.write_u64(rom_hash);
+        writer
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // Fixed size data, the reader has to know the length
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    // Length of a variable size list, written before its items
+    pub fn write_len(&mut self, len: usize) {
+        self.write_u32(len as u32);
+    }
+
+    pub fn write_model(&mut self, model: Model) {
+        let id = MODELS.iter().position(|&m| m == model).unwrap();
+        self.write_u8(id as u8);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    // Reads the body of a state, see open
+    pub fn new(body: &'a [u8]) -> Self {
+        StateReader { data: body }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.data.len() < len {
+            return Err(SaveStateError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::Corrupted),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn read_bytes(&mut self, bytes: &mut [u8]) -> Result<(), SaveStateError> {
+        bytes.copy_from_slice(self.take(bytes.len())?);
+        Ok(())
+    }
+
+    // Lengths can't be larger than what is left, which also stops huge allocations
+    pub fn read_len(&mut self) -> Result<usize, SaveStateError> {
+        let len = self.read_u32()? as usize;
+        if len > self.data.len() {
+            return Err(SaveStateError::Truncated);
+        }
+        Ok(len)
+    }
+
+    pub fn read_model(&mut self) -> Result<Model, SaveStateError> {
+        let id = self.read_u8()?;
+        MODELS
+            .get(id as usize)
+            .copied()
+            .ok_or(SaveStateError::Corrupted)
+    }
+
+    // Every byte of the state has to be used
+    pub fn finish(self) -> Result<(), SaveStateError> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err(SaveStateError::Corrupted)
+        }
+    }
+}
+
+// Checks the header of a state against the running machine and returns its body, migrated to
+// the current version
+pub fn open(state: &[u8], model: Model, rom_hash: u64) -> Result<Vec<u8>, SaveStateError> {
+    if state.len() < HEADER_SIZE || &state[0..4] != MAGIC {
+        return Err(SaveStateError::NotASaveState);
+    }
+    let mut header = StateReader::new(&state[4..HEADER_SIZE]);
+    let version = header.read_u16()?;
+    if version == 0 {
+        return Err(SaveStateError::NotASaveState);
+    }
+    if version > VERSION {
+        return Err(SaveStateError::UnsupportedVersion(version));
+    }
+    let saved_model = header.read_model()?;
+    let saved_hash = header.read_u64()?;
+    if saved_model != model {
+        return Err(SaveStateError::WrongModel {
+            saved: saved_model,
+            running: model,
+        });
+    }
+    if saved_hash != rom_hash {
+        return Err(SaveStateError::WrongRom);
+    }
+
+    let mut body = state[HEADER_SIZE..].to_vec();
+    for migration in &MIGRATIONS[version as usize - 1..] {
+        body = migration(body)?;
+    }
+    Ok(body)
+}
@@ -0,0 +1,169 @@
+use super::save_state::{SaveStateError, StateReader, StateWriter};
+
+// Peripherals that are only brought up to date when something happens to them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Peripheral {
+    Ppu,
+    Timer,
+    Dma,
+    // The devices plugged into the bus, see BusDevice::next_event
+    Devices,
+    Serial,
+}
+
+const PERIPHERALS: [Peripheral; 5] = [
+    Peripheral::Ppu,
+    Peripheral::Timer,
+    Peripheral::Dma,
+    Peripheral::Devices,
+    Peripheral::Serial,
+];
+
+struct Entry {
+    peripheral: Peripheral,
+    synced_at: u64,
+    next_event: u64,
+}
+
+// Keeps time in dots since power-on. Instead of being ticked on every M-cycle, a peripheral
+// registers when its next event is due (a mode change, a timer overflow...) and the bus only
+// runs it then, or when the CPU touches its registers, catching up on the dots it missed
+pub struct Scheduler {
+    now: u64,
+    entries: Vec<Entry>,
+    // The soonest next_event, so most M-cycles only need one comparison
+    earliest: u64,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Scheduler::new()
+    }
+}
+
+impl Scheduler {
+    // Every peripheral starts up to date at power-on, with nothing scheduled
+    pub fn new() -> Self {
+        let mut scheduler = Scheduler {
+            now: 0,
+            entries: Vec::new(),
+            earliest: u64::MAX,
+        };
+        scheduler.add_missing_entries();
+        scheduler
+    }
+
+    fn add_missing_entries(&mut self) {
+        for peripheral in PERIPHERALS {
+            if !self.entries.iter().any(|e| e.peripheral == peripheral) {
+                self.entries.push(Entry {
+                    peripheral,
+                    synced_at: self.now,
+                    next_event: u64::MAX,
+                });
+            }
+        }
+    }
+
+    fn update_earliest(&mut self) {
+        self.earliest = self
+            .entries
+            .iter()
+            .map(|e| e.next_event)
+            .min()
+            .unwrap_or(u64::MAX);
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn advance(&mut self, dots: u64) {
+        self.now += dots;
+    }
+
+    fn entry(&mut self, peripheral: Peripheral) -> &mut Entry {
+        self.entries
+            .iter_mut()
+            .find(|e| e.peripheral == peripheral)
+            .expect("every peripheral has an entry")
+    }
+
+    // Sets the next event of the peripheral, `dots` from now
+    pub fn schedule(&mut self, peripheral: Peripheral, dots: u64) {
+        let now = self.now;
+        self.entry(peripheral).next_event = now + dots;
+        self.update_earliest();
+    }
+
+    // The peripheral has nothing coming up until it's accessed
+    pub fn cancel(&mut self, peripheral: Peripheral) {
+        self.entry(peripheral).next_event = u64::MAX;
+        self.update_earliest();
+    }
+
+    // True if any peripheral has an event due
+    pub fn any_due(&self) -> bool {
+        self.earliest <= self.now
+    }
+
+    pub fn is_due(&self, peripheral: Peripheral) -> bool {
+        self.entries
+            .iter()
+            .any(|e| e.peripheral == peripheral && e.next_event <= self.now)
+    }
+
+    // Dots since the peripheral was last brought up to date
+    pub fn elapsed(&self, peripheral: Peripheral) -> u64 {
+        let entry = self.entries.iter().find(|e| e.peripheral == peripheral);
+        self.now - entry.expect("every peripheral has an entry").synced_at
+    }
+
+    // Returns the dots the peripheral has to run to be up to date and marks it as up to date
+    pub fn catch_up(&mut self, peripheral: Peripheral) -> u64 {
+        let now = self.now;
+        let entry = self.entry(peripheral);
+        let dots = now - entry.synced_at;
+        entry.synced_at = now;
+        dots
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u64(self.now);
+        state.write_len(self.entries.len());
+        for entry in &self.entries {
+            state.write_u8(entry.peripheral as u8);
+            state.write_u64(entry.synced_at);
+            state.write_u64(entry.next_event);
+        }
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.now = state.read_u64()?;
+        self.entries.clear();
+        for _ in 0..state.read_len()? {
+            let peripheral = match state.read_u8()? {
+                0 => Peripheral::Ppu,
+                1 => Peripheral::Timer,
+                2 => Peripheral::Dma,
+                3 => Peripheral::Devices,
+                4 => Peripheral::Serial,
+                _ => return Err(SaveStateError::Corrupted),
+            };
+            let synced_at = state.read_u64()?;
+            let next_event = state.read_u64()?;
+            if synced_at > self.now || self.entries.iter().any(|e| e.peripheral == peripheral) {
+                return Err(SaveStateError::Corrupted);
+            }
+            self.entries.push(Entry {
+                peripheral,
+                synced_at,
+                next_event,
+            });
+        }
+        // Peripherals added since the state was saved start from now
+        self.add_missing_entries();
+        self.update_earliest();
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+use super::consts::SB_ADDR;
+use super::model::Model;
+use super::save_state::{SaveStateError, StateReader, StateWriter};
+#[cfg(test)]
+mod tests;
+
+// A byte takes 8 bits at 8192 Hz, 32 times faster with the CGB fast clock
+const TRANSFER_M_CYCLES: u16 = 1024;
+const FAST_TRANSFER_M_CYCLES: u16 = 32;
+
+// SB and SC with nothing plugged into the link port. Transfers started with the internal clock
+// shift SB out and shift 0xFF in, then request the serial interrupt. Transfers waiting for an
+// external clock never end. Like the timer it's only run when it's accessed or its interrupt
+// is due, see AddrSpace::sync_serial
+#[derive(Clone, Copy)]
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    // M-cycles left in the transfer in progress
+    remaining: u16,
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Serial::new()
+    }
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Serial {
+            sb: 0,
+            sc: 0,
+            remaining: 0,
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        if addr == SB_ADDR {
+            self.sb
+        } else {
+            self.sc
+        }
+    }
+
+    // Returns the byte sent if the write started a transfer. Only the CGB has the clock speed bit
+    pub fn write(&mut self, addr: u16, data: u8, model: Model) -> Option<u8> {
+        if addr == SB_ADDR {
+            self.sb = data;
+            return None;
+        }
+        self.sc = data;
+        if data & 0x81 != 0x81 {
+            self.remaining = 0;
+            return None;
+        }
+        self.remaining = if model.is_cgb() && data & 0x02 != 0 {
+            FAST_TRANSFER_M_CYCLES
+        } else {
+            TRANSFER_M_CYCLES
+        };
+        Some(self.sb)
+    }
+
+    // Runs the port for whole M-cycles, returns true if a transfer ended and requested the
+    // interrupt
+    pub fn advance(&mut self, m_cycles: u64) -> bool {
+        if self.remaining == 0 {
+            return false;
+        }
+        if m_cycles < self.remaining as u64 {
+            self.remaining -= m_cycles as u16;
+            return false;
+        }
+        self.remaining = 0;
+        self.sb = 0xFF;
+        self.sc &= 0x7F;
+        true
+    }
+
+    // M-cycles until the transfer in progress ends, None if there is none
+    pub fn m_cycles_to_interrupt(&self) -> Option<u64> {
+        (self.remaining > 0).then_some(self.remaining as u64)
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.sb);
+        state.write_u8(self.sc);
+        state.write_u16(self.remaining);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.sb = state.read_u8()?;
+        self.sc = state.read_u8()?;
+        self.remaining = state.read_u16()?;
+        if self.remaining > TRANSFER_M_CYCLES || (self.remaining > 0 && self.sc & 0x81 != 0x81) {
+            return Err(SaveStateError::Corrupted);
+        }
+        Ok(())
+    }
+}
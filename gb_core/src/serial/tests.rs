@@ -0,0 +1,30 @@
+use super::*;
+use crate::consts::SC_ADDR;
+
+#[test]
+fn test_transfer() {
+    let mut serial = Serial::new();
+    serial.write(SB_ADDR, 0x42, Model::DMG);
+    // The external clock never comes
+    assert_eq!(serial.write(SC_ADDR, 0x80, Model::DMG), None);
+    assert_eq!(serial.m_cycles_to_interrupt(), None);
+
+    assert_eq!(serial.write(SC_ADDR, 0x81, Model::DMG), Some(0x42));
+    assert_eq!(serial.m_cycles_to_interrupt(), Some(1024));
+    assert!(!serial.advance(1023));
+    assert_eq!(serial.read(SC_ADDR), 0x81);
+    assert!(serial.advance(1));
+    assert_eq!(serial.read(SB_ADDR), 0xFF);
+    assert_eq!(serial.read(SC_ADDR), 0x01);
+    assert!(!serial.advance(5000));
+}
+
+#[test]
+fn test_fast_clock() {
+    let mut serial = Serial::new();
+    serial.write(SC_ADDR, 0x83, Model::CGB);
+    assert_eq!(serial.m_cycles_to_interrupt(), Some(32));
+    // The DMG ignores the speed bit
+    serial.write(SC_ADDR, 0x83, Model::DMG);
+    assert_eq!(serial.m_cycles_to_interrupt(), Some(1024));
+}
@@ -0,0 +1,187 @@
+use super::consts::{DIV_ADDR, TIMA_ADDR, TMA_ADDR};
+use super::save_state::{SaveStateError, StateReader, StateWriter};
+#[cfg(test)]
+mod tests;
+
+// Bit of the counter TIMA follows, by TAC clock select: 4096, 262144, 65536 and 16384 Hz
+const TAC_BITS: [u32; 4] = [9, 3, 5, 7];
+
+// DIV, TIMA, TMA and TAC. DIV is the high byte of a counter incremented every dot and TIMA
+// counts the falling edges of one of its bits while TAC enables it, so writing DIV or TAC can
+// increment TIMA as well. The timer is only run when it's accessed or its interrupt is due,
+// see AddrSpace::sync_timer
+#[derive(Clone, Copy)]
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    // TIMA overflowed on the last M-cycle and reads 0, it's reloaded from TMA on the next one
+    overflowed: bool,
+    // TIMA was reloaded on the last M-cycle. Writes to TIMA are lost and writes to TMA also
+    // go to TIMA
+    reloading: bool,
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Timer::new()
+    }
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Timer {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            overflowed: false,
+            reloading: false,
+        }
+    }
+
+    // Starts from the registers the boot ROM leaves behind, see Model::post_boot_io
+    pub fn from_registers(io: &[u8; 0x80]) -> Self {
+        Timer {
+            counter: (io[0x04] as u16) << 8,
+            tima: io[0x05],
+            tma: io[0x06],
+            tac: io[0x07] & 0x07,
+            overflowed: false,
+            reloading: false,
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            DIV_ADDR => (self.counter >> 8) as u8,
+            TIMA_ADDR => self.tima,
+            TMA_ADDR => self.tma,
+            _ => self.tac,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        let input = self.input();
+        match addr {
+            DIV_ADDR => self.counter = 0,
+            TIMA_ADDR if self.reloading => {}
+            // Writing TIMA right after it overflowed cancels the reload and the interrupt
+            TIMA_ADDR => {
+                self.tima = data;
+                self.overflowed = false;
+            }
+            TMA_ADDR => {
+                self.tma = data;
+                if self.reloading {
+                    self.tima = data;
+                }
+            }
+            _ => self.tac = data & 0x07,
+        }
+        if input && !self.input() {
+            self.increment();
+        }
+    }
+
+    // Runs the timer for whole M-cycles, returns true if it requested its interrupt
+    pub fn advance(&mut self, mut m_cycles: u64) -> bool {
+        let mut interrupt = false;
+        while m_cycles > 0 {
+            // Until the next increment only the counter moves
+            if !self.overflowed {
+                let skip = (self.m_cycles_to_increment() - 1).min(m_cycles);
+                if skip > 0 {
+                    self.counter = self.counter.wrapping_add((skip as u16).wrapping_mul(4));
+                    self.reloading = false;
+                    m_cycles -= skip;
+                    continue;
+                }
+            }
+            interrupt |= self.step();
+            m_cycles -= 1;
+        }
+        interrupt
+    }
+
+    // M-cycles until the interrupt is requested, None while TIMA is stopped
+    pub fn m_cycles_to_interrupt(&self) -> Option<u64> {
+        if self.overflowed {
+            return Some(1);
+        }
+        if !self.enabled() {
+            return None;
+        }
+        let increments = 0xFF - self.tima as u64;
+        Some(self.m_cycles_to_increment() + increments * (self.period() / 4) + 1)
+    }
+
+    fn step(&mut self) -> bool {
+        self.reloading = false;
+        let reload = self.overflowed;
+        if reload {
+            self.overflowed = false;
+            self.tima = self.tma;
+            self.reloading = true;
+        }
+        let input = self.input();
+        self.counter = self.counter.wrapping_add(4);
+        if input && !self.input() {
+            self.increment();
+        }
+        reload
+    }
+
+    fn increment(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        self.overflowed |= overflow;
+    }
+
+    fn enabled(&self) -> bool {
+        self.tac & 0x04 != 0
+    }
+
+    // Dots between two increments
+    fn period(&self) -> u64 {
+        2 << TAC_BITS[(self.tac & 0x03) as usize]
+    }
+
+    // What TIMA counts the falling edges of
+    fn input(&self) -> bool {
+        let bit = TAC_BITS[(self.tac & 0x03) as usize];
+        self.enabled() && self.counter & (1 << bit) != 0
+    }
+
+    fn m_cycles_to_increment(&self) -> u64 {
+        if !self.enabled() {
+            return u64::MAX;
+        }
+        let phase = self.counter as u64 % self.period();
+        (self.period() - phase) / 4
+    }
+
+    pub fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.counter);
+        state.write_u8(self.tima);
+        state.write_u8(self.tma);
+        state.write_u8(self.tac);
+        state.write_bool(self.overflowed);
+        state.write_bool(self.reloading);
+    }
+
+    pub fn load_state(&mut self, state: &mut StateReader) -> Result<(), SaveStateError> {
+        self.counter = state.read_u16()?;
+        self.tima = state.read_u8()?;
+        self.tma = state.read_u8()?;
+        self.tac = state.read_u8()?;
+        self.overflowed = state.read_bool()?;
+        self.reloading = state.read_bool()?;
+        // The counter only moves by whole M-cycles
+        if self.counter & 0x03 != 0 || self.tac > 0x07 {
+            return Err(SaveStateError::Corrupted);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+use super::*;
+use crate::consts::TAC_ADDR;
+
+fn running_timer(tima: u8, tma: u8) -> Timer {
+    let mut timer = Timer::new();
+    timer.write(TIMA_ADDR, tima);
+    timer.write(TMA_ADDR, tma);
+    // 262144 Hz, an increment every 4 M-cycles
+    timer.write(TAC_ADDR, 0x05);
+    timer
+}
+
+#[test]
+fn test_div() {
+    let mut timer = Timer::new();
+    timer.advance(63);
+    assert_eq!(timer.read(DIV_ADDR), 0);
+    timer.advance(1);
+    assert_eq!(timer.read(DIV_ADDR), 1);
+    timer.write(DIV_ADDR, 0x55);
+    assert_eq!(timer.read(DIV_ADDR), 0);
+    // TIMA doesn't count while TAC is off
+    assert_eq!(timer.read(TIMA_ADDR), 0);
+}
+
+#[test]
+fn test_overflow_and_reload() {
+    let mut timer = running_timer(0xFE, 0x42);
+    assert_eq!(timer.m_cycles_to_interrupt(), Some(9));
+    assert!(!timer.advance(8));
+    // TIMA reads 0 for an M-cycle before the reload
+    assert_eq!(timer.read(TIMA_ADDR), 0x00);
+    assert!(timer.advance(1));
+    assert_eq!(timer.read(TIMA_ADDR), 0x42);
+    // A write to TIMA on the reload cycle is lost, one to TMA goes through
+    timer.write(TIMA_ADDR, 0x10);
+    timer.write(TMA_ADDR, 0x20);
+    assert_eq!(timer.read(TIMA_ADDR), 0x20);
+}
+
+#[test]
+fn test_write_cancels_reload() {
+    let mut timer = running_timer(0xFF, 0x42);
+    timer.advance(4);
+    assert_eq!(timer.read(TIMA_ADDR), 0x00);
+    timer.write(TIMA_ADDR, 0x10);
+    assert!(!timer.advance(1));
+    assert_eq!(timer.read(TIMA_ADDR), 0x10);
+}
+
+#[test]
+fn test_div_write_increments_tima() {
+    let mut timer = running_timer(0x00, 0x00);
+    // Bit 3 of the counter is set halfway between two increments
+    timer.advance(2);
+    timer.write(DIV_ADDR, 0);
+    assert_eq!(timer.read(TIMA_ADDR), 0x01);
+    // Turning the timer off while the bit is set counts as a falling edge as well
+    timer.advance(2);
+    timer.write(TAC_ADDR, 0x01);
+    assert_eq!(timer.read(TIMA_ADDR), 0x02);
+}
+
+#[test]
+fn test_advance_skips_like_stepping() {
+    for tac in [0x04, 0x05, 0x06, 0x07, 0x00] {
+        let mut skipping = running_timer(0xF0, 0xE0);
+        skipping.write(TAC_ADDR, tac);
+        let mut stepping = skipping;
+        for length in [1, 7, 300, 5000, 64, 3] {
+            let predicted = skipping.m_cycles_to_interrupt();
+            let interrupt = skipping.advance(length);
+            let first = (1..=length).find(|_| stepping.advance(1));
+            for _ in first.unwrap_or(length)..length {
+                stepping.advance(1);
+            }
+            assert_eq!(interrupt, first.is_some(), "TAC {:02X}", tac);
+            if let Some(m_cycle) = first {
+                assert_eq!(predicted, Some(m_cycle), "TAC {:02X}", tac);
+            }
+            for addr in DIV_ADDR..=TAC_ADDR {
+                assert_eq!(skipping.read(addr), stepping.read(addr), "TAC {:02X}", tac);
+            }
+        }
+    }
+}
@@ -0,0 +1,360 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::process;
+
+mod script;
+#[cfg(test)]
+mod tests;
+
+use gb_core::consts::IE_ADDR;
+use gb_core::cpu::Event;
+use gb_core::gameboy::GameBoy;
+use gb_core::hash;
+use gb_core::model::Model;
+use gb_core::movie::Movie;
+use gb_core::ppu::{HEIGHT, WIDTH};
+use script::{Script, BUTTONS};
+
+// Exit codes
+const CONDITION_MET: i32 = 0;
+const FAILED: i32 = 1;
+const TIMED_OUT: i32 = 2;
+const LOCKED_UP: i32 = 3;
+const USAGE_ERROR: i32 = 4;
+
+const USAGE: &str = "\
+Usage: gb_headless <rom> [options]
+
+Runs the ROM without a window until one of the --until conditions is met or the frame limit is
+reached, then prints the frame hash and the serial output.
+
+Machine:
+  --model <name>          dmg, mgb, sgb, cgb... (default dmg), with --boot-rom or --fast-boot
+  --boot-rom <file>       Runs a boot ROM dump of the model
+  --fast-boot             Skips the boot ROM
+  --input <file>          Scripted joypad input, see the README
+  --movie <file>          Plays a movie recorded with gb_minifb --record
+
+Run conditions:
+  --frames <n>            Frame limit (default 3600)
+  --until-pc <addr>       PC reaches the address
+  --until-mem <addr>=<v>  The byte at the address reads the value
+  --until-serial <text>   The serial output contains the text
+  --until-loop            An instruction jumps to itself and no interrupt can leave it
+  --fail-serial <text>    Fails as soon as the serial output contains the text
+
+Output:
+  --png <file>            Writes the last frame
+  --expect-hash <hash>    Fails if the hash of the last frame differs
+
+Addresses, values and hashes are hexadecimal.
+
+Exit codes: 0 condition met, or frame limit reached without conditions, 1 --fail-serial or
+--expect-hash failed, 2 frame limit reached first, 3 CPU locked up, 4 bad arguments or files";
+
+struct Options {
+    rom: String,
+    model: Option<Model>,
+    boot_rom: Option<String>,
+    fast_boot: bool,
+    input: Option<String>,
+    movie: Option<String>,
+    frames: u32,
+    until_pc: Option<u16>,
+    until_mem: Option<(u16, u8)>,
+    until_serial: Option<String>,
+    until_loop: bool,
+    fail_serial: Option<String>,
+    png: Option<String>,
+    expect_hash: Option<u64>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            rom: String::new(),
+            model: None,
+            boot_rom: None,
+            fast_boot: false,
+            input: None,
+            movie: None,
+            frames: 3600,
+            until_pc: None,
+            until_mem: None,
+            until_serial: None,
+            until_loop: false,
+            fail_serial: None,
+            png: None,
+            expect_hash: None,
+        }
+    }
+}
+
+impl Options {
+    fn has_condition(&self) -> bool {
+        self.until_pc.is_some()
+            || self.until_mem.is_some()
+            || self.until_serial.is_some()
+            || self.until_loop
+    }
+}
+
+// Why the run stopped
+enum Outcome {
+    FrameLimit,
+    PC,
+    Memory,
+    Serial,
+    Loop,
+    FailSerial,
+    Locked { pc: u16, opcode: u8 },
+}
+
+fn usage_error(message: impl std::fmt::Display) -> ! {
+    eprintln!("{}", message);
+    process::exit(USAGE_ERROR);
+}
+
+fn parse_hex<T>(
+    text: &str,
+    parse: fn(&str, u32) -> Result<T, std::num::ParseIntError>,
+) -> Result<T, String> {
+    let digits = text.trim_start_matches("0x").trim_start_matches('$');
+    parse(digits, 16).map_err(|_| format!("Invalid hexadecimal {}", text))
+}
+
+fn parse_args() -> Options {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| usage_error(format!("Missing value for {}", arg)))
+        };
+        match arg.as_str() {
+            "--model" => {
+                let name = value();
+                options.model = Some(
+                    Model::from_name(&name)
+                        .unwrap_or_else(|| usage_error(format!("Unknown model {}", name))),
+                );
+            }
+            "--boot-rom" => options.boot_rom = Some(value()),
+            "--fast-boot" => options.fast_boot = true,
+            "--input" => options.input = Some(value()),
+            "--movie" => options.movie = Some(value()),
+            "--frames" => {
+                let frames = value();
+                options.frames = frames
+                    .parse()
+                    .unwrap_or_else(|_| usage_error(format!("Invalid frame count {}", frames)));
+            }
+            "--until-pc" => {
+                let addr = parse_hex(&value(), u16::from_str_radix);
+                options.until_pc = Some(addr.unwrap_or_else(|e| usage_error(e)));
+            }
+            "--until-mem" => {
+                let condition = value();
+                let (addr, data) = condition.split_once('=').unwrap_or_else(|| {
+                    usage_error(format!("Expected <addr>=<value>, got {}", condition))
+                });
+                let addr = parse_hex(addr, u16::from_str_radix);
+                let data = parse_hex(data, u8::from_str_radix);
+                options.until_mem = Some((
+                    addr.unwrap_or_else(|e| usage_error(e)),
+                    data.unwrap_or_else(|e| usage_error(e)),
+                ));
+            }
+            "--until-serial" => options.until_serial = Some(value()),
+            "--until-loop" => options.until_loop = true,
+            "--fail-serial" => options.fail_serial = Some(value()),
+            "--png" => options.png = Some(value()),
+            "--expect-hash" => {
+                let hash = parse_hex(&value(), u64::from_str_radix);
+                options.expect_hash = Some(hash.unwrap_or_else(|e| usage_error(e)));
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(CONDITION_MET);
+            }
+            _ if arg.starts_with("--") => {
+                usage_error(format!("Unknown option {}\n\n{}", arg, USAGE))
+            }
+            _ => options.rom = arg,
+        }
+    }
+    if options.rom.is_empty() {
+        usage_error(USAGE);
+    }
+    // Without a boot ROM the emulator always starts as a DMG
+    if options.model.is_some() && options.boot_rom.is_none() && !options.fast_boot {
+        usage_error("--model needs --boot-rom or --fast-boot");
+    }
+    if options.input.is_some() && options.movie.is_some() {
+        usage_error("--input and --movie can't be used together");
+    }
+    options
+}
+
+fn read_file(filename: &str) -> Vec<u8> {
+    std::fs::read(filename).unwrap_or_else(|e| usage_error(format!("{}: {}", filename, e)))
+}
+
+fn contains(haystack: &[u8], needle: &str) -> bool {
+    needle.is_empty()
+        || haystack
+            .windows(needle.len())
+            .any(|window| window == needle.as_bytes())
+}
+
+fn apply_script(gameboy: &mut GameBoy, script: Option<&Script>, frame: u32) {
+    if let Some(held) = script.and_then(|script| script.changes_at(frame)) {
+        for &(_, button) in &BUTTONS {
+            gameboy.set_button(button, held.contains(&button));
+        }
+    }
+}
+
+// True if the last instruction jumped to itself and no interrupt can take the CPU out of the
+// loop. A halted CPU stays on the same PC as well, but an interrupt can wake it up
+fn stuck(gameboy: &GameBoy, previous_pc: u16) -> bool {
+    let cpu = &gameboy.cpu;
+    let interrupts_enabled = cpu.ime || cpu.schedule_ime;
+    let interrupts_unmasked = gameboy.addr_space.read(IE_ADDR) & 0x1F != 0;
+    cpu.pc == previous_pc
+        && !cpu.halted
+        && !cpu.stopped
+        && !(interrupts_enabled && interrupts_unmasked)
+}
+
+// Runs instruction by instruction so the conditions are checked after each one
+fn run(gameboy: &mut GameBoy, options: &Options, script: Option<&Script>) -> (Outcome, u32) {
+    let mut frame = 0;
+    let mut serial_len = 0;
+    apply_script(gameboy, script, frame);
+    while frame < options.frames {
+        let pc = gameboy.cpu.pc;
+        gameboy.step();
+        if let Some(Event::Locked { pc, opcode }) = gameboy.cpu.event.take() {
+            return (Outcome::Locked { pc, opcode }, frame);
+        }
+        if gameboy.frame_ready() {
+            frame += 1;
+            apply_script(gameboy, script, frame);
+        }
+
+        // The serial output only has to be searched when it grows
+        let output = gameboy.addr_space.serial_log();
+        if output.len() != serial_len {
+            serial_len = output.len();
+            if let Some(text) = &options.fail_serial {
+                if contains(output, text) {
+                    return (Outcome::FailSerial, frame);
+                }
+            }
+            if let Some(text) = &options.until_serial {
+                if contains(output, text) {
+                    return (Outcome::Serial, frame);
+                }
+            }
+        }
+        if options.until_pc == Some(gameboy.cpu.pc) {
+            return (Outcome::PC, frame);
+        }
+        if let Some((addr, data)) = options.until_mem {
+            if gameboy.addr_space.read(addr) == data {
+                return (Outcome::Memory, frame);
+            }
+        }
+        if options.until_loop && stuck(gameboy, pc) {
+            return (Outcome::Loop, frame);
+        }
+    }
+    (Outcome::FrameLimit, frame)
+}
+
+// The framebuffer as 8-bit RGB, which is what the PNG holds and what the hash is taken over
+fn frame_rgb(gameboy: &GameBoy) -> Vec<u8> {
+    gameboy
+        .framebuffer()
+        .iter()
+        .flat_map(|&pixel| [(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8])
+        .collect()
+}
+
+fn write_png(filename: &str, rgb: &[u8]) -> Result<(), png::EncodingError> {
+    let file = BufWriter::new(File::create(filename)?);
+    let mut encoder = png::Encoder::new(file, WIDTH as u32, HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgb)
+}
+
+fn main() {
+    let options = parse_args();
+    let cart = read_file(&options.rom);
+    let model = options.model.unwrap_or(Model::DMG);
+
+    let mut gameboy = if options.fast_boot {
+        GameBoy::post_boot(model, Some(cart))
+    } else if let Some(boot_rom) = &options.boot_rom {
+        GameBoy::with_boot_rom(model, read_file(boot_rom), Some(cart))
+            .unwrap_or_else(|e| usage_error(e))
+    } else {
+        GameBoy::new(Some(cart))
+    };
+
+    gameboy.addr_space.log_serial(true);
+
+    let script = options.input.as_ref().map(|input| {
+        let text = String::from_utf8_lossy(&read_file(input)).into_owned();
+        Script::parse(&text).unwrap_or_else(|e| usage_error(format!("{}: {}", input, e)))
+    });
+    if let Some(movie) = &options.movie {
+        Movie::from_bytes(&read_file(movie))
+            .and_then(|m| gameboy.play_movie(m))
+            .unwrap_or_else(|e| usage_error(format!("{}: {}", movie, e)));
+    }
+
+    let (outcome, frames) = run(&mut gameboy, &options, script.as_ref());
+
+    let rgb = frame_rgb(&gameboy);
+    let hash = hash::fnv1a(&rgb);
+    if let Some(filename) = &options.png {
+        write_png(filename, &rgb).unwrap_or_else(|e| usage_error(format!("{}: {}", filename, e)));
+    }
+
+    let (reason, mut code) = match outcome {
+        Outcome::FrameLimit if options.has_condition() => {
+            (String::from("frame limit reached"), TIMED_OUT)
+        }
+        Outcome::FrameLimit => (String::from("frame limit reached"), CONDITION_MET),
+        Outcome::PC => (String::from("PC reached"), CONDITION_MET),
+        Outcome::Memory => (String::from("memory value matched"), CONDITION_MET),
+        Outcome::Serial => (String::from("serial output matched"), CONDITION_MET),
+        Outcome::Loop => (String::from("infinite loop"), CONDITION_MET),
+        Outcome::FailSerial => (String::from("serial output failure matched"), FAILED),
+        Outcome::Locked { pc, opcode } => (
+            format!("CPU locked up by opcode {:02x} at {:04x}", opcode, pc),
+            LOCKED_UP,
+        ),
+    };
+    println!("Stopped: {}", reason);
+    println!("Frames: {}", frames);
+    println!("PC: {:04x}", gameboy.cpu.pc);
+    println!(
+        "Serial: {}",
+        String::from_utf8_lossy(gameboy.addr_space.serial_log())
+    );
+    println!("Frame hash: {:016x}", hash);
+    if let Some(expected) = options.expect_hash {
+        if hash != expected {
+            println!("Expected frame hash {:016x}", expected);
+            if code == CONDITION_MET {
+                code = FAILED;
+            }
+        }
+    }
+    process::exit(code);
+}
@@ -0,0 +1,78 @@
+use gb_core::joypad::Button;
+#[cfg(test)]
+mod tests;
+
+pub const BUTTONS: [(&str, Button); 8] = [
+    ("right", Button::Right),
+    ("left", Button::Left),
+    ("up", Button::Up),
+    ("down", Button::Down),
+    ("a", Button::A),
+    ("b", Button::B),
+    ("select", Button::Select),
+    ("start", Button::Start),
+];
+
+// Scripted joypad input, one line per change: the frame it starts on and the buttons held from
+// then on, joined with '+', or '-' for none. Frames count from 0 and go up, '#' starts a comment
+//
+//   60 start
+//   62 -
+//   120 a+right
+pub struct Script {
+    changes: Vec<(u32, Vec<Button>)>,
+}
+
+impl Script {
+    pub fn parse(text: &str) -> Result<Script, String> {
+        let mut changes: Vec<(u32, Vec<Button>)> = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", i + 1, message);
+            let mut fields = line.split_whitespace();
+            let frame = fields.next().unwrap_or_default();
+            let frame = frame
+                .parse::<u32>()
+                .map_err(|_| error(format!("invalid frame {}", frame)))?;
+            if matches!(changes.last(), Some(&(last, _)) if frame <= last) {
+                return Err(error(format!(
+                    "frame {} isn't after the previous one",
+                    frame
+                )));
+            }
+            let buttons = match fields.next() {
+                Some("-") => Vec::new(),
+                Some(names) => names
+                    .split('+')
+                    .map(|name| {
+                        button(name).ok_or_else(|| error(format!("unknown button {}", name)))
+                    })
+                    .collect::<Result<_, _>>()?,
+                None => return Err(error(String::from("missing buttons"))),
+            };
+            if fields.next().is_some() {
+                return Err(error(String::from("too many fields")));
+            }
+            changes.push((frame, buttons));
+        }
+        Ok(Script { changes })
+    }
+
+    // The buttons to hold from the given frame on, None if they don't change on it
+    pub fn changes_at(&self, frame: u32) -> Option<&[Button]> {
+        self.changes
+            .binary_search_by_key(&frame, |&(start, _)| start)
+            .ok()
+            .map(|index| self.changes[index].1.as_slice())
+    }
+}
+
+fn button(name: &str) -> Option<Button> {
+    BUTTONS
+        .iter()
+        .find(|(button_name, _)| button_name.eq_ignore_ascii_case(name))
+        .map(|&(_, button)| button)
+}
@@ -0,0 +1,38 @@
+use super::*;
+
+#[test]
+fn test_parse() {
+    let script = Script::parse(
+        "# Skips the title screen\n\
+         60 start\n\
+         \n\
+         62 -   # releases it\n\
+         120 A+right\n",
+    )
+    .unwrap();
+    assert_eq!(script.changes_at(60), Some(&[Button::Start][..]));
+    assert_eq!(script.changes_at(61), None);
+    assert_eq!(script.changes_at(62), Some(&[][..]));
+    assert_eq!(
+        script.changes_at(120),
+        Some(&[Button::A, Button::Right][..])
+    );
+    assert_eq!(script.changes_at(0), None);
+}
+
+#[test]
+fn test_parse_errors() {
+    let error = |text| Script::parse(text).err().unwrap();
+    assert_eq!(
+        error("10 a\n10 b"),
+        "line 2: frame 10 isn't after the previous one"
+    );
+    assert_eq!(
+        error("10 a\n5 b"),
+        "line 2: frame 5 isn't after the previous one"
+    );
+    assert_eq!(error("10 a+turbo"), "line 1: unknown button turbo");
+    assert_eq!(error("ten a"), "line 1: invalid frame ten");
+    assert_eq!(error("10"), "line 1: missing buttons");
+    assert_eq!(error("10 a b"), "line 1: too many fields");
+}
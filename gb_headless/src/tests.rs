@@ -0,0 +1,57 @@
+use super::*;
+
+// A ROM running the program from 0x100, with RETI as the VBlank handler
+fn gameboy_running(program: &[u8]) -> GameBoy {
+    let mut rom = vec![0; 0x8000];
+    rom[0x40] = 0xD9;
+    rom[0x100..0x100 + program.len()].copy_from_slice(program);
+    GameBoy::post_boot(Model::DMG, Some(rom))
+}
+
+fn until_loop() -> Options {
+    Options {
+        frames: 3,
+        until_loop: true,
+        ..Options::default()
+    }
+}
+
+#[test]
+fn test_until_loop() {
+    // DI; JR -2
+    let mut gameboy = gameboy_running(&[0xF3, 0x18, 0xFE]);
+    let (outcome, frames) = run(&mut gameboy, &until_loop(), None);
+    assert!(matches!(outcome, Outcome::Loop));
+    assert_eq!(frames, 0);
+    assert_eq!(gameboy.cpu.pc, 0x101);
+
+    // EI; JR -2 without any interrupt enabled in IE
+    let mut gameboy = gameboy_running(&[0xAF, 0xE0, 0xFF, 0xFB, 0x18, 0xFE]);
+    let (outcome, _) = run(&mut gameboy, &until_loop(), None);
+    assert!(matches!(outcome, Outcome::Loop));
+}
+
+#[test]
+fn test_until_loop_waits_for_interrupts() {
+    // LD A,1; LDH (IE),A; EI; JR -2. The VBlank interrupt leaves the loop every frame
+    let mut gameboy = gameboy_running(&[0x3E, 0x01, 0xE0, 0xFF, 0xFB, 0x18, 0xFE]);
+    let (outcome, frames) = run(&mut gameboy, &until_loop(), None);
+    assert!(matches!(outcome, Outcome::FrameLimit));
+    assert_eq!(frames, 3);
+}
+
+#[test]
+fn test_contains() {
+    assert!(contains(b"cpu_instrs\n\nPassed\n", "Passed"));
+    assert!(!contains(b"Pass", "Passed"));
+    assert!(contains(b"", ""));
+}
+
+#[test]
+fn test_parse_hex() {
+    assert_eq!(parse_hex("c000", u16::from_str_radix), Ok(0xC000));
+    assert_eq!(parse_hex("0xFF", u8::from_str_radix), Ok(0xFF));
+    assert_eq!(parse_hex("$42", u8::from_str_radix), Ok(0x42));
+    assert!(parse_hex("100", u8::from_str_radix).is_err());
+    assert!(parse_hex("xyz", u16::from_str_radix).is_err());
+}
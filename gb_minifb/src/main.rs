@@ -1,19 +1,33 @@
 #![feature(mixed_integer_ops)]
 
-use gb_core::joypad;
-use minifb::{Key, Window, WindowOptions};
+use minifb::{Key, KeyRepeat, Scale, Window, WindowOptions};
 use std::io::Read;
 
-use gb_core::addr::*;
-use gb_core::consts::*;
+mod font;
+mod menu;
+mod osd;
+mod pacing;
+mod palette;
+
 use gb_core::cpu::*;
-use gb_core::instructions::*;
-use gb_core::interrupts::*;
+use gb_core::gameboy::*;
 use gb_core::joypad::*;
-use gb_core::ppu::*;
+use gb_core::model::*;
+use gb_core::movie::Movie;
+use gb_core::ppu::{HEIGHT, WIDTH};
+use menu::Action;
+use osd::Osd;
+use pacing::{Pacer, FRAME_M_CYCLES};
+use palette::PALETTES;
 
-const WIDTH: usize = 160;
-const HEIGHT: usize = 144;
+const SCALES: [(Scale, &str); 4] = [
+    (Scale::X1, "1X"),
+    (Scale::X2, "2X"),
+    (Scale::X4, "4X"),
+    (Scale::X8, "8X"),
+];
+const REWIND_BUDGET: usize = 64 << 20;
+const SLOW_MOTION_SPEED: f64 = 0.25;
 
 fn read_cartridge(filename: &str) -> std::io::Result<Vec<u8>> {
     let mut file = std::fs::File::open(filename)?;
@@ -22,6 +36,24 @@ fn read_cartridge(filename: &str) -> std::io::Result<Vec<u8>> {
     Ok(data)
 }
 
+// The window doesn't limit its update rate, the Pacer keeps the speed
+fn open_window(title: &str, scale: Scale) -> Window {
+    let mut window = Window::new(
+        title,
+        WIDTH,
+        HEIGHT,
+        WindowOptions {
+            scale,
+            ..Default::default()
+        },
+    )
+    .unwrap_or_else(|e| {
+        panic!("{}", e);
+    });
+    window.limit_update_rate(None);
+    window
+}
+
 fn main() {
     // let cart = read_cartridge("tests/01-special.gb").unwrap(); //PASS
     // let cart = read_cartridge("tests/02-interrupts.gb").unwrap();
@@ -35,80 +67,210 @@ fn main() {
     // let cart = read_cartridge("tests/10-bit ops.gb").unwrap(); //PASS!
     // let cart = read_cartridge("tests/11-op a,(hl).gb").unwrap(); //PASS
 
-    let cart = read_cartridge("bgbtest.gb").unwrap();
+    // Usage: gb_minifb [rom] [--model dmg|mgb|sgb|cgb|...] [--boot-rom file] [--fast-boot]
+    //                  [--record movie | --play movie] [--ff-speed N]
+    let mut rom = String::from("bgbtest.gb");
+    let mut model = None;
+    let mut boot_rom = None;
+    let mut fast_boot = false;
+    let mut record = None;
+    let mut play = None;
+    let mut ff_speed = 4;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--model" => {
+                let name = args.next().unwrap_or_default();
+                model = Some(
+                    Model::from_name(&name).unwrap_or_else(|| panic!("Unknown model {}", name)),
+                );
+            }
+            "--boot-rom" => boot_rom = args.next(),
+            "--fast-boot" => fast_boot = true,
+            "--record" => record = args.next(),
+            "--play" => play = args.next(),
+            "--ff-speed" => {
+                let speed = args.next().unwrap_or_default();
+                ff_speed = speed
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|&speed| speed > 0)
+                    .unwrap_or_else(|| panic!("Invalid fast-forward speed {}", speed));
+            }
+            _ => rom = arg,
+        }
+    }
 
-    let mut addr_space = AddrSpace::new(DMG, Some(cart));
-    let mut cpu = CPU::new();
-    let mut ppu = PPU::new();
-    let mut joypad_state = JoypadState::new();
+    // Without a boot ROM the emulator always starts as a DMG
+    if model.is_some() && !fast_boot && boot_rom.is_none() {
+        panic!("--model needs --boot-rom or --fast-boot");
+    }
+    let model = model.unwrap_or(Model::DMG);
+    // Recording starts over from power-on, which would drop the movie being played
+    if record.is_some() && play.is_some() {
+        panic!("--record and --play can't be used together");
+    }
 
-    let mut window = Window::new(
-        &addr_space.game_title(),
-        WIDTH,
-        HEIGHT,
-        WindowOptions {
-            scale: minifb::Scale::X4,
-            ..Default::default()
-        },
-    )
-    .unwrap_or_else(|e| {
-        panic!("{}", e);
-    });
+    let cart = read_cartridge(&rom).unwrap();
 
-    while window.is_open() && !window.is_key_down(Key::Escape) {
-        if cpu.pc == 0x100 {
-            addr_space.deactivate_bios();
-        }
+    let mut gameboy = if fast_boot {
+        GameBoy::post_boot(model, Some(cart))
+    } else if let Some(boot_rom) = boot_rom {
+        let boot_rom = read_cartridge(&boot_rom).unwrap();
+        GameBoy::with_boot_rom(model, boot_rom, Some(cart)).unwrap_or_else(|e| panic!("{}", e))
+    } else {
+        GameBoy::new(Some(cart))
+    };
+    println!("Game title: {}", gameboy.addr_space.game_title());
+    println!("Cartridge type: {}", gameboy.addr_space.cartridge_type());
+    println!("Rom size: {}", gameboy.addr_space.rom_size());
+    let mut access_restrictions = true;
 
-        let instr = cpu.next_instr(&addr_space);
+    // Movies start at power-on and are written when the emulator is closed
+    if let Some(play) = &play {
+        Movie::from_bytes(&read_cartridge(play).unwrap())
+            .and_then(|movie| gameboy.play_movie(movie))
+            .unwrap_or_else(|e| panic!("{}: {}", play, e));
+    }
+    if record.is_some() {
+        gameboy.start_recording(true);
+    }
 
-        let cycles = exec_instruction(instr, &mut cpu, &mut addr_space) as u32;
+    let title = gameboy.addr_space.game_title();
+    let mut scale = 2;
+    let mut window = open_window(&title, SCALES[scale].0);
+    let mut palette = 0;
+    let mut paused = false;
+    // Frame advance pauses without the menu
+    let mut show_menu = false;
+    let mut slow_motion = false;
+    let mut pacer = Pacer::new();
+    let mut osd = Osd::new();
+    // What the window shows: the frame in the chosen palette with the OSD on top. The PPU
+    // framebuffer itself is never drawn on
+    let mut display = vec![0; WIDTH * HEIGHT];
+    // Holding Backspace goes back two frames per frame
+    gameboy.enable_rewind(2, REWIND_BUDGET);
 
-        let mut vblank = ppu.tick(cycles * 4, &mut addr_space);
+    let keys = [
+        (Key::Right, Button::Right),
+        (Key::Left, Button::Left),
+        (Key::Up, Button::Up),
+        (Key::Down, Button::Down),
+        (Key::Z, Button::A),
+        (Key::X, Button::B),
+        (Key::A, Button::Select),
+        (Key::S, Button::Start),
+    ];
 
-        //Handle interrupts
-        let extra_cycles = handle_interrupts(&mut cpu, &mut addr_space);
-        if extra_cycles > 0 {
-            vblank = vblank || ppu.tick(extra_cycles * 4, &mut addr_space);
+    // Escape or P pauses and opens the menu, N pauses and then advances one frame per press.
+    // Holding Tab fast-forwards, F4 toggles slow motion. F1 toggles the VRAM/OAM access
+    // restrictions (handy when debugging homebrew) and F3 the FPS counter
+    while window.is_open() {
+        let mut advance = false;
+        if paused {
+            match menu::pressed_action(&window) {
+                Some(Action::Resume) => paused = false,
+                Some(Action::FrameAdvance) => {
+                    advance = true;
+                    show_menu = false;
+                }
+                Some(Action::Reset) => {
+                    // Resetting stops the movie, a recording starts over
+                    if gameboy.recording_movie() {
+                        gameboy.start_recording(true);
+                        osd.message("RESET, RECORDING RESTARTED");
+                    } else {
+                        gameboy.reset();
+                        osd.message("RESET");
+                    }
+                    paused = false;
+                }
+                Some(Action::Scale) => {
+                    scale = (scale + 1) % SCALES.len();
+                    window = open_window(&title, SCALES[scale].0);
+                }
+                Some(Action::Palette) => palette = (palette + 1) % PALETTES.len(),
+                Some(Action::Quit) => break,
+                None => {}
+            }
+        } else if window.is_key_pressed(Key::Escape, KeyRepeat::No)
+            || window.is_key_pressed(Key::P, KeyRepeat::No)
+        {
+            paused = true;
+            show_menu = true;
+        } else if window.is_key_pressed(Key::N, KeyRepeat::No) {
+            paused = true;
+            show_menu = false;
+        } else if window.is_key_pressed(Key::F1, KeyRepeat::No) {
+            access_restrictions = !access_restrictions;
+            gameboy
+                .addr_space
+                .set_access_restrictions(access_restrictions);
+            let state = if access_restrictions { "ON" } else { "OFF" };
+            osd.message(format!("ACCESS RESTRICTIONS {}", state));
+        } else if window.is_key_pressed(Key::F3, KeyRepeat::No) {
+            osd.show_fps = !osd.show_fps;
+        } else if window.is_key_pressed(Key::F4, KeyRepeat::No) {
+            slow_motion = !slow_motion;
+            let state = if slow_motion { "ON" } else { "OFF" };
+            osd.message(format!("SLOW MOTION {}", state));
         }
 
-        if vblank {
-            window
-                .update_with_buffer(&ppu.pixels, WIDTH, HEIGHT)
-                .unwrap();
-        }
+        let fast_forward = !paused && window.is_key_down(Key::Tab);
+        let speed = if fast_forward {
+            ff_speed as f64
+        } else if slow_motion {
+            SLOW_MOTION_SPEED
+        } else {
+            1.0
+        };
 
-        joypad_state.reset();
-        if window.is_key_down(Key::Right) {
-            joypad_state.right = true;
-        }
-        if window.is_key_down(Key::Left) {
-            joypad_state.left = true;
-        }
-        if window.is_key_down(Key::Up) {
-            joypad_state.up = true;
-        }
-        if window.is_key_down(Key::Down) {
-            joypad_state.down = true;
-        }
-        if window.is_key_down(Key::Z) {
-            joypad_state.a = true;
-        }
-        if window.is_key_down(Key::X) {
-            joypad_state.b = true;
-        }
-        if window.is_key_down(Key::A) {
-            joypad_state.select = true;
-        }
-        if window.is_key_down(Key::S) {
-            joypad_state.start = true;
+        // M-cycles emulated for this presented frame, a paused machine still waits a frame
+        let mut cycles = FRAME_M_CYCLES;
+        if !paused && window.is_key_down(Key::Backspace) {
+            if gameboy.rewind_frame() {
+                osd.message("REWIND");
+            }
+            osd.frame_presented(1);
+        } else if !paused || advance {
+            for (key, button) in keys {
+                gameboy.set_button(button, window.is_key_down(key));
+            }
+            // Only the last of the fast-forwarded frames is shown, the others aren't drawn
+            let frames = if fast_forward { ff_speed } else { 1 };
+            cycles = 0;
+            for frame in 0..frames {
+                gameboy.ppu.skip_rendering = frame + 1 < frames;
+                let playing = gameboy.playing_movie();
+                cycles += gameboy.run_frame();
+                if playing && !gameboy.playing_movie() {
+                    osd.message("MOVIE FINISHED");
+                }
+
+                if let Some(Event::Locked { pc, opcode }) = gameboy.cpu.event.take() {
+                    println!("CPU locked up by opcode {:02x} at {:04x}", opcode, pc);
+                    osd.message(format!("CPU LOCKED UP AT {:04X}", pc));
+                }
+            }
+            gameboy.ppu.skip_rendering = false;
+            osd.frame_presented(frames);
         }
 
-        joypad_state.update_joypad(&mut addr_space);
+        PALETTES[palette].apply(gameboy.framebuffer(), &mut display);
+        if paused && show_menu {
+            menu::draw(&mut display, SCALES[scale].1, PALETTES[palette].name);
+        }
+        osd.draw(&mut display);
+        pacer.wait(cycles, speed);
+        window.update_with_buffer(&display, WIDTH, HEIGHT).unwrap();
+    }
 
-        if cpu.schedule_ime {
-            cpu.schedule_ime = false;
-            cpu.ime = true;
+    if let Some(record) = &record {
+        if let Some(movie) = gameboy.stop_movie() {
+            std::fs::write(record, movie.to_bytes())
+                .unwrap_or_else(|e| panic!("{}: {}", record, e));
+            println!("Recorded {} frames to {}", movie.frames(), record);
         }
     }
 }
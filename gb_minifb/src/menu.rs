@@ -0,0 +1,62 @@
+use minifb::{Key, KeyRepeat, Window};
+
+use crate::font::{self, GLYPH_HEIGHT};
+use crate::osd;
+use gb_core::ppu::{HEIGHT, WIDTH};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    Resume,
+    FrameAdvance,
+    Reset,
+    Scale,
+    Palette,
+    Quit,
+}
+
+// Hotkeys of the pause menu. The game doesn't get any input while it's open, so they can share
+// keys with the joypad
+const ITEMS: [(Key, Action); 7] = [
+    (Key::Escape, Action::Resume),
+    (Key::P, Action::Resume),
+    (Key::N, Action::FrameAdvance),
+    (Key::R, Action::Reset),
+    (Key::W, Action::Scale),
+    (Key::C, Action::Palette),
+    (Key::Q, Action::Quit),
+];
+
+const LINE_HEIGHT: usize = GLYPH_HEIGHT + 4;
+
+pub fn pressed_action(window: &Window) -> Option<Action> {
+    ITEMS
+        .iter()
+        .find(|(key, _)| window.is_key_pressed(*key, KeyRepeat::No))
+        .map(|&(_, action)| action)
+}
+
+// Dims the frame and lists the hotkeys, with the current scale and palette
+pub fn draw(display: &mut [u32], scale: &str, palette: &str) {
+    for pixel in display.iter_mut() {
+        *pixel = 0xFF000000 | ((*pixel >> 2) & 0x3F3F3F);
+    }
+    let lines = [
+        String::from("PAUSED"),
+        String::new(),
+        String::from("P  RESUME"),
+        String::from("N  NEXT FRAME"),
+        String::from("R  RESET"),
+        format!("W  SCALE {}", scale),
+        format!("C  PALETTE {}", palette),
+        String::from("Q  QUIT"),
+    ];
+    let top = (HEIGHT - lines.len() * LINE_HEIGHT) / 2;
+    for (i, line) in lines.iter().enumerate() {
+        let x = if i == 0 {
+            (WIDTH - font::text_width(line)) / 2
+        } else {
+            40
+        };
+        osd::draw_shadowed(display, x, top + i * LINE_HEIGHT, line);
+    }
+}
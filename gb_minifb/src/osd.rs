@@ -0,0 +1,79 @@
+use std::time::{Duration, Instant};
+
+use crate::font::{self, GLYPH_HEIGHT};
+use gb_core::ppu::{HEIGHT, WIDTH};
+
+// The DMG refresh rate, used to show the speed relative to the real hardware
+const GB_FPS: f64 = 4194304.0 / 70224.0;
+const MESSAGE_TIME: Duration = Duration::from_secs(2);
+const TEXT_COLOR: u32 = 0xFFFFFFFF;
+const SHADOW_COLOR: u32 = 0xFF000000;
+
+// On-screen display drawn over the displayed copy of the frame: an FPS and speed counter in the
+// top left corner and short-lived messages at the bottom
+pub struct Osd {
+    pub show_fps: bool,
+    message: Option<(String, Instant)>,
+    // Frames presented and emulated since `counting_since`
+    frames: u32,
+    emulated_frames: u32,
+    counting_since: Instant,
+    fps: f64,
+    // Emulated frames per second
+    speed: f64,
+}
+
+impl Osd {
+    pub fn new() -> Self {
+        Osd {
+            show_fps: false,
+            message: None,
+            frames: 0,
+            emulated_frames: 0,
+            counting_since: Instant::now(),
+            fps: 0.0,
+            speed: 0.0,
+        }
+    }
+
+    // Shows `text` for a couple of seconds, replacing the previous message
+    pub fn message(&mut self, text: impl Into<String>) {
+        self.message = Some((text.into(), Instant::now()));
+    }
+
+    // Called for every presented frame with the number of frames emulated for it, which is
+    // more than one when fast-forwarding. The counter is refreshed twice per second
+    pub fn frame_presented(&mut self, emulated_frames: u32) {
+        self.frames += 1;
+        self.emulated_frames += emulated_frames;
+        let elapsed = self.counting_since.elapsed();
+        if elapsed >= Duration::from_millis(500) {
+            self.fps = self.frames as f64 / elapsed.as_secs_f64();
+            self.speed = self.emulated_frames as f64 / elapsed.as_secs_f64();
+            self.frames = 0;
+            self.emulated_frames = 0;
+            self.counting_since = Instant::now();
+        }
+    }
+
+    pub fn draw(&mut self, display: &mut [u32]) {
+        if self.show_fps {
+            let text = format!("{:.0} FPS {:.0}%", self.fps, self.speed * 100.0 / GB_FPS);
+            draw_shadowed(display, 2, 2, &text);
+        }
+        if let Some((_, shown_at)) = &self.message {
+            if shown_at.elapsed() > MESSAGE_TIME {
+                self.message = None;
+            }
+        }
+        if let Some((text, _)) = &self.message {
+            draw_shadowed(display, 2, HEIGHT - GLYPH_HEIGHT - 3, text);
+        }
+    }
+}
+
+// Text with a one pixel shadow so it stays readable over any background
+pub fn draw_shadowed(display: &mut [u32], x: usize, y: usize, text: &str) {
+    font::draw_text(display, WIDTH, x + 1, y + 1, text, SHADOW_COLOR);
+    font::draw_text(display, WIDTH, x, y, text, TEXT_COLOR);
+}
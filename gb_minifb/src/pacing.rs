@@ -0,0 +1,36 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+// The CPU runs 1048576 M-cycles per second and a frame takes 17556 of them, about 59.7275 Hz
+pub const M_CYCLES_PER_SECOND: f64 = 1048576.0;
+pub const FRAME_M_CYCLES: u32 = 17556;
+
+// Past this the host is considered too slow to catch up, the pacing starts over from now
+// instead of running flat out until it has made up for the lost time
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+// Keeps the emulation in step with real time, based on the emulated cycles rather than on the
+// host's refresh rate
+pub struct Pacer {
+    // When the cycles emulated so far are due
+    deadline: Instant,
+}
+
+impl Pacer {
+    pub fn new() -> Self {
+        Pacer {
+            deadline: Instant::now(),
+        }
+    }
+
+    // Waits until `m_cycles` more have taken their time at `speed` times the real hardware
+    pub fn wait(&mut self, m_cycles: u32, speed: f64) {
+        self.deadline += Duration::from_secs_f64(m_cycles as f64 / (M_CYCLES_PER_SECOND * speed));
+        let now = Instant::now();
+        if self.deadline > now {
+            thread::sleep(self.deadline - now);
+        } else if now - self.deadline > MAX_LAG {
+            self.deadline = now;
+        }
+    }
+}
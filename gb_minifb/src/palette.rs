@@ -0,0 +1,40 @@
+// Colours the four DMG shades are shown with. The emulated framebuffer always uses greys, the
+// palette is only applied to the copy that gets displayed
+pub struct Palette {
+    pub name: &'static str,
+    // Lightest to darkest
+    pub shades: [u32; 4],
+}
+
+pub const PALETTES: [Palette; 4] = [
+    Palette {
+        name: "GREY",
+        shades: [0xFFFFFFFF, 0xFFAAAAAA, 0xFF555555, 0xFF000000],
+    },
+    Palette {
+        name: "DMG GREEN",
+        shades: [0xFF9BBC0F, 0xFF8BAC0F, 0xFF306230, 0xFF0F380F],
+    },
+    Palette {
+        name: "POCKET",
+        shades: [0xFFC4CFA1, 0xFF8B956D, 0xFF4D533C, 0xFF1F1F1F],
+    },
+    Palette {
+        name: "LIGHT",
+        shades: [0xFF00B581, 0xFF009A71, 0xFF00694A, 0xFF004F3B],
+    },
+];
+
+impl Palette {
+    // Copies the PPU output into `display` with this palette's colours
+    pub fn apply(&self, pixels: &[u32], display: &mut [u32]) {
+        for (shown, &pixel) in display.iter_mut().zip(pixels) {
+            *shown = match pixel {
+                0xFFFFFFFF => self.shades[0],
+                0xFFAAAAAA => self.shades[1],
+                0xFF555555 => self.shades[2],
+                _ => self.shades[3],
+            };
+        }
+    }
+}
@@ -1,8 +1,7 @@
 mod utils;
 
 use gb_core::{
-    addr::AddrSpace, consts::DMG, cpu::CPU, debug, instructions::*, interrupts::handle_interrupts,
-    joypad::JoypadState, ppu::PPU,
+    cpu::CPU, debug, gameboy, joypad::Button, model::Model, movie::Movie, ppu::Renderer,
 };
 use wasm_bindgen::prelude::*;
 
@@ -23,84 +22,113 @@ macro_rules! log {
 
 #[wasm_bindgen]
 pub struct GameBoy {
-    addr_space: AddrSpace,
-    cpu: CPU,
-    ppu: PPU,
-    joypad_state: JoypadState,
+    gameboy: gameboy::GameBoy,
+    // RGBA copy of the framebuffer handed out to JS by `screen`
+    screen: Vec<u8>,
 }
 
 #[wasm_bindgen]
 impl GameBoy {
     pub fn new(cart: Vec<u8>) -> GameBoy {
-        let addr_space = AddrSpace::new(DMG, Some(cart));
-        let cpu = CPU::new();
-        let ppu = PPU::new();
-        let joypad_state = JoypadState::new();
-        GameBoy {
-            cpu,
-            ppu,
-            addr_space,
-            joypad_state,
-        }
+        GameBoy::from(gameboy::GameBoy::new(Some(cart)))
+    }
+
+    // Starts the cartridge with the state the boot ROM of `model` leaves behind
+    pub fn fast_boot(cart: Vec<u8>, model: &str) -> Result<GameBoy, JsValue> {
+        let model = parse_model(model)?;
+        let gameboy = gameboy::GameBoy::post_boot(model, Some(cart));
+        Ok(GameBoy::from(gameboy))
+    }
+
+    // Runs a boot ROM dump provided by the user before the cartridge
+    pub fn with_boot_rom(
+        cart: Vec<u8>,
+        boot_rom: Vec<u8>,
+        model: &str,
+    ) -> Result<GameBoy, JsValue> {
+        let model = parse_model(model)?;
+        let gameboy = gameboy::GameBoy::with_boot_rom(model, boot_rom, Some(cart))
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(GameBoy::from(gameboy))
     }
 
     pub fn empty() -> GameBoy {
-        let addr_space = AddrSpace::new(DMG, None);
-        let cpu = CPU::new();
-        let ppu = PPU::new();
-        let joypad_state = JoypadState::new();
-        GameBoy {
-            cpu,
-            ppu,
-            addr_space,
-            joypad_state,
-        }
+        GameBoy::from(gameboy::GameBoy::new(None))
     }
 
     pub fn reset(&mut self) {
-        self.cpu = CPU::new();
-        self.ppu = PPU::new();
-        self.joypad_state = JoypadState::new();
-        self.addr_space.reset();
+        self.gameboy.reset();
     }
 
-    pub fn tick(&mut self) {
-        let mut vblank = false;
-        while !vblank {
-            vblank = self.instr_tick();
-        }
+    // Keeps a snapshot every `interval` frames, using at most about `budget` bytes
+    pub fn enable_rewind(&mut self, interval: u32, budget: usize) {
+        self.gameboy.enable_rewind(interval, budget);
     }
 
-    pub fn instr_tick(&mut self) -> bool {
-         
-        if self.cpu.pc == 0x100 {
-            self.addr_space.deactivate_bios();
-        }
+    // Goes back one snapshot, false if there's nothing to go back to
+    pub fn rewind_frame(&mut self) -> bool {
+        self.gameboy.rewind_frame()
+    }
 
-        let instr = self.cpu.next_instr(&self.addr_space);
+    // Records the input of every frame, from power-on or from the current state
+    pub fn start_recording(&mut self, from_power_on: bool) {
+        self.gameboy.start_recording(from_power_on);
+    }
 
-        let cycles = exec_instruction(instr, &mut self.cpu, &mut self.addr_space) as u32;
+    // The recorded or played movie, if there is one
+    pub fn stop_movie(&mut self) -> Option<Vec<u8>> {
+        self.gameboy.stop_movie().map(|movie| movie.to_bytes())
+    }
 
-        let mut vblank = self.ppu.tick(cycles * 4, &mut self.addr_space);
+    pub fn play_movie(&mut self, movie: &[u8]) -> Result<(), JsValue> {
+        Movie::from_bytes(movie)
+            .and_then(|movie| self.gameboy.play_movie(movie))
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
 
-        let extra_cycles = handle_interrupts(&mut self.cpu, &mut self.addr_space);
-        if extra_cycles > 0 {
-            vblank = vblank || self.ppu.tick(extra_cycles * 4, &mut self.addr_space);
-        }
+    pub fn playing_movie(&self) -> bool {
+        self.gameboy.playing_movie()
+    }
 
-        self.joypad_state.update_joypad(&mut self.addr_space);
+    pub fn save_state(&self) -> Vec<u8> {
+        self.gameboy.save_state()
+    }
 
-        if self.cpu.schedule_ime {
-            self.cpu.schedule_ime = false;
-            self.cpu.ime = true;
-        }
-        vblank
+    // Fails if the state was saved with another cartridge or model, or by a newer version
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), JsValue> {
+        self.gameboy
+            .load_state(state)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
     }
 
-    pub fn screen(&self) -> *const u8 {
-        let mut pixels = [0_u8; 4 * 160 * 144];
-        let mut i = 0;
-        for c in self.ppu.pixels {
+    pub fn tick(&mut self) {
+        self.gameboy.run_frame();
+    }
+
+    pub fn instr_tick(&mut self) -> bool {
+        self.gameboy.step();
+        self.gameboy.frame_ready()
+    }
+
+    // True once an undefined opcode has hung the CPU
+    pub fn locked(&self) -> bool {
+        self.gameboy.cpu.locked
+    }
+
+    pub fn set_fifo_renderer(&mut self, value: bool) {
+        self.gameboy.ppu.set_renderer(if value {
+            Renderer::Fifo
+        } else {
+            Renderer::Scanline
+        });
+    }
+
+    pub fn set_access_restrictions(&mut self, value: bool) {
+        self.gameboy.addr_space.set_access_restrictions(value);
+    }
+
+    pub fn screen(&mut self) -> *const u8 {
+        for (i, &c) in self.gameboy.framebuffer().iter().enumerate() {
             let x = match c {
                 0xFF000000 => 0x0 as u8,
                 0xFFAAAAAA => 0xAA as u8,
@@ -109,43 +137,43 @@ impl GameBoy {
                 _ => 0xFF as u8,
             };
 
-            pixels[(i * 4)..((i + 1) * 4)].clone_from_slice(&[x, x, x, 0xFF]);
-            i += 1;
+            self.screen[(i * 4)..((i + 1) * 4)].clone_from_slice(&[x, x, x, 0xFF]);
         }
-        pixels.as_ptr()
+        self.screen.as_ptr()
     }
 
     pub fn set_up(&mut self, value: bool) {
-        self.joypad_state.up = value;
+        self.gameboy.set_button(Button::Up, value);
     }
     pub fn set_left(&mut self, value: bool) {
-        self.joypad_state.left = value;
+        self.gameboy.set_button(Button::Left, value);
     }
     pub fn set_right(&mut self, value: bool) {
-        self.joypad_state.right = value;
+        self.gameboy.set_button(Button::Right, value);
     }
     pub fn set_down(&mut self, value: bool) {
-        self.joypad_state.down = value;
+        self.gameboy.set_button(Button::Down, value);
     }
     pub fn set_a(&mut self, value: bool) {
-        self.joypad_state.a = value;
+        self.gameboy.set_button(Button::A, value);
     }
     pub fn set_b(&mut self, value: bool) {
-        self.joypad_state.b = value;
+        self.gameboy.set_button(Button::B, value);
     }
     pub fn set_select(&mut self, value: bool) {
-        self.joypad_state.select = value;
+        self.gameboy.set_button(Button::Select, value);
     }
     pub fn set_start(&mut self, value: bool) {
-        self.joypad_state.start = value;
+        self.gameboy.set_button(Button::Start, value);
     }
 
     pub fn get_memory(&self, buffer_size: u16) -> String {
+        let cpu = &self.gameboy.cpu;
         let mut addr: u16 = 0;
         let mut final_string = String::new();
-        while addr <= self.cpu.pc + buffer_size {
-            let (str, len) = debug::instr_name(addr, &self.cpu, &self.addr_space);
-            if addr >= self.cpu.pc {
+        while addr <= cpu.pc + buffer_size {
+            let (str, len) = debug::instr_name(addr, cpu, &self.gameboy.addr_space);
+            if addr >= cpu.pc {
                 final_string += &str;
                 final_string += "</br>";
             }
@@ -155,7 +183,16 @@ impl GameBoy {
     }
 
     pub fn cpu_debug(&self) -> CPUDebug {
-        CPUDebug(self.cpu)
+        CPUDebug(self.gameboy.cpu)
+    }
+}
+
+impl From<gameboy::GameBoy> for GameBoy {
+    fn from(gameboy: gameboy::GameBoy) -> Self {
+        GameBoy {
+            gameboy,
+            screen: vec![0; 4 * 160 * 144],
+        }
     }
 }
 
@@ -205,3 +242,7 @@ impl CPUDebug {
         self.0.zero_flag()
     }
 }
+
+fn parse_model(name: &str) -> Result<Model, JsValue> {
+    Model::from_name(name).ok_or_else(|| JsValue::from_str(&format!("Unknown model {}", name)))
+}